@@ -1,6 +1,10 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, TransferChecked};
-use anchor_lang::solana_program::program_pack::Pack;
+use anchor_spl::token_2022::spl_token_2022::extension::{
+    transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+};
+use anchor_spl::token_2022::spl_token_2022::state::{Account as SplAccount, Mint as SplMint};
+use anchor_spl::token_interface::{self, Approve, Mint, Revoke, TokenAccount, TokenInterface, TransferChecked};
+use anchor_lang::solana_program::hash::hash;
 use anchor_lang::solana_program::rent::Rent;
 
 #[cfg(not(feature = "no-entrypoint"))]
@@ -19,14 +23,55 @@ security_txt! {
 
 declare_id!("HaioYYCZuXWxiHjFG9i8MnzAH6dFdgh1E1eCGjTwWzb");
 
-/// Bump the reserved size to at least 1 KB so later upgrades
-/// (e.g. multi-token support) can reuse the same account without realloc.
-/// Configuration space for PDAs (includes discriminator)
-pub const CONFIG_SPACE: usize = 1024; // Fixed size for membership config
+/// Initial space for config PDAs (includes discriminator).
+/// Trimmed or grown to the serialized size by `resize_config` on every write.
+pub const CONFIG_SPACE: usize = 1024;
 
-/// Fixed size constants for deposit realloc calculations
-pub const DEPOSIT_FIXED: usize = 32 + 32 + 4; // admin + deposit_wallet + vec len prefix
-pub const PUBKEY_SIZE: usize = 32; // single pubkey size
+/// Membership state space: discriminator + user + plan + tier + mint + started_at + expires_at + trial_ends_at
+/// + payments + last_payer + last_amount + last_paid_at + paid_value + paid_from + bump
+pub const MEMBERSHIP_SPACE: usize = 8 + 32 + 1 + 1 + 32 + 8 + 8 + 8 + 8 + 32 + 8 + 8 + 8 + 8 + 1;
+
+/// Coupon space: discriminator + code_hash + discount + mint + max_redemptions + redemptions + expires_at + bump
+pub const COUPON_SPACE: usize = 8 + 32 + (1 + 8) + (1 + 32) + 4 + 4 + 8 + 1;
+
+/// Coupon redemption marker space: discriminator + coupon + user + redeemed_at + bump
+pub const COUPON_REDEMPTION_SPACE: usize = 8 + 32 + 32 + 8 + 1;
+
+/// Referral stats space: discriminator + referrer + mint + referrals + total_earned + bump
+pub const REFERRAL_STATS_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 1;
+
+/// Deposit ledger space: discriminator + user + mint + total_deposited + deposit_count + last_deposit_at
+/// + cap_hour + hourly_amounts + bump
+pub const DEPOSIT_LEDGER_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 8 + 8 + 8 * DEPOSIT_CAP_BUCKETS + 1;
+
+/// Payment reference marker space: discriminator + reference + payer + created_at + bump
+pub const PAYMENT_REFERENCE_SPACE: usize = 8 + 32 + 32 + 8 + 1;
+
+/// Maximum length of a membership tier name in bytes
+pub const MAX_TIER_NAME_LEN: usize = 32;
+
+/// Membership period lengths in seconds
+pub const MONTHLY_PERIOD: i64 = 30 * 86_400;
+pub const YEARLY_PERIOD: i64 = 365 * 86_400;
+
+/// Auto-renewal state space: discriminator + user + mint + token_account + created_at + bump
+pub const AUTO_RENEWAL_SPACE: usize = 8 + 32 + 32 + 32 + 8 + 1;
+
+/// How long before expiry `crank_renewal` may pull the next monthly payment
+pub const RENEWAL_WINDOW: i64 = 86_400;
+
+/// Oldest oracle price accepted for USD-priced fees, in seconds
+pub const MAX_PRICE_AGE: i64 = 60;
+
+/// Widest oracle confidence interval accepted, in basis points of the price
+pub const MAX_PRICE_CONF_BPS: u64 = 200;
+
+/// Hourly buckets of the rolling per-user deposit cap: the current hour and the 24
+/// before it, so every 24h span is covered by one window
+pub const DEPOSIT_CAP_BUCKETS: usize = 25;
+
+/// Delay before a queued treasury / deposit wallet change can be applied
+pub const DESTINATION_TIMELOCK: i64 = 48 * 3_600;
 
 #[program]
 pub mod transaction_gateway {
@@ -37,13 +82,29 @@ pub mod transaction_gateway {
         ctx: Context<InitializeMembershipConfig>,
         admin: Pubkey,
         treasury: Pubkey,
+        tiers: Vec<MembershipTier>,
         fees: Vec<MembershipFee>,
     ) -> Result<()> {
+        require_keys_neq!(admin, Pubkey::default(), ErrorCode::InvalidAdmin);
+        validate_destination(&treasury)?;
+        require_keys_eq!(treasury, ctx.accounts.treasury.key(), ErrorCode::InvalidDestination);
+        validate_tiers(&tiers)?;
+        validate_fees(&fees, &tiers)?;
+        validate_mint_accounts(fees.iter().map(|f| &f.mint), &[], ctx.remaining_accounts)?;
+        
         let config = &mut ctx.accounts.config;
         config.admin = admin;
         config.treasury = treasury;
+        config.tiers = tiers;
         config.fees = fees;
         
+        resize_config(
+            config,
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
         msg!("Membership config initialized");
         Ok(())
     }
@@ -55,53 +116,267 @@ pub mod transaction_gateway {
         deposit_wallet: Pubkey,
         allowed_mints: Vec<Pubkey>,
     ) -> Result<()> {
+        require_keys_neq!(admin, Pubkey::default(), ErrorCode::InvalidAdmin);
+        validate_destination(&deposit_wallet)?;
+        require_keys_eq!(deposit_wallet, ctx.accounts.deposit_wallet.key(), ErrorCode::InvalidDestination);
+        validate_unique_mints(&allowed_mints)?;
+        validate_mint_accounts(allowed_mints.iter(), &[], ctx.remaining_accounts)?;
+        
         let config = &mut ctx.accounts.config;
         config.admin = admin;
         config.deposit_wallet = deposit_wallet;
         config.allowed_mints = allowed_mints;
         
+        resize_config(
+            config,
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
         msg!("Deposit config initialized");
         Ok(())
     }
 
-    /// Pay membership fee
-    pub fn pay_membership(
-        ctx: Context<PayMembership>,
+    /// Pay membership fee for `tier`.
+    /// While a membership is active only the same tier can be renewed.
+    /// With `coupon_code`, the `coupon` and `coupon_redemption` accounts must be
+    /// passed and `amount` must match the discounted price.
+    /// With a `referrer`, `referral_bps` of `amount` goes to the referrer instead
+    /// of the treasury and is tallied in the referrer's `ReferralStats`.
+    /// With revenue splits configured, one recipient account per split is passed
+    /// as remaining accounts (see `pay_treasury`).
+    /// USD-priced fees need `plan` and the fee's `price_feed`; `amount` is then the
+    /// most the user accepts to pay. Otherwise `plan` may be omitted and is
+    /// resolved from the exact `amount`.
+    /// An optional `reference` (e.g. an order id) is reported in `MembershipPaid`;
+    /// with the `reference_marker` account the payer can only ever pay it once.
+    pub fn pay_membership<'info>(
+        ctx: Context<'_, '_, '_, 'info, PayMembership<'info>>,
+        tier: u8,
+        mint: Pubkey,
+        amount: u64,
+        plan: Option<MembershipPlan>,
+        coupon_code: Option<String>,
+        reference: Option<[u8; 32]>,
+    ) -> Result<()> {
+        let beneficiary = ctx.accounts.user.key();
+        process_membership_payment(ctx, beneficiary, tier, mint, amount, plan, coupon_code, reference)
+    }
+
+    /// Pay membership fee for `tier` on behalf of `beneficiary`.
+    /// Same rules and accounts as `pay_membership`, except that `membership` is the
+    /// beneficiary's `MembershipState` PDA; the signer only pays. Gifts can't convert
+    /// a trial to another tier, nor replace another payer's self-refundable payment.
+    #[allow(clippy::too_many_arguments)]
+    pub fn gift_membership<'info>(
+        ctx: Context<'_, '_, '_, 'info, PayMembership<'info>>,
+        beneficiary: Pubkey,
+        tier: u8,
         mint: Pubkey,
         amount: u64,
+        plan: Option<MembershipPlan>,
+        coupon_code: Option<String>,
+        reference: Option<[u8; 32]>,
     ) -> Result<()> {
+        require_keys_neq!(beneficiary, Pubkey::default(), ErrorCode::InvalidBeneficiary);
+        process_membership_payment(ctx, beneficiary, tier, mint, amount, plan, coupon_code, reference)
+    }
+
+    /// Start a free membership trial on `cfg.trial_tier` for `cfg.trial_days`.
+    /// Each wallet can trial once, and only without an active membership.
+    pub fn start_trial(ctx: Context<StartTrial>) -> Result<()> {
         let config = &ctx.accounts.cfg;
         
-        // Find matching fee
-        let fee = config.fees.iter()
-            .find(|f| f.mint == mint)
-            .ok_or(ErrorCode::UnsupportedMint)?;
+        // Emergency stop
+        require!(!config.paused, ErrorCode::GatewayPaused);
+        require!(config.trial_enabled, ErrorCode::TrialsDisabled);
+        require!(config.tiers.iter().any(|t| t.id == config.trial_tier), ErrorCode::UnknownTier);
         
-        // Validate amount
-        require!(amount == fee.monthly_fee || amount == fee.yearly_fee, ErrorCode::WrongFee);
+        let now = Clock::get()?.unix_timestamp;
+        let membership = &mut ctx.accounts.membership;
+        require!(membership.trial_ends_at == 0, ErrorCode::TrialAlreadyUsed);
+        require!(!membership.is_active(now), ErrorCode::MembershipActive);
         
-        // Transfer assets
+        let expires_at = now
+            .checked_add(config.trial_days as i64 * 86_400)
+            .ok_or(ErrorCode::MathOverflow)?;
+        membership.user = ctx.accounts.user.key();
+        membership.plan = MembershipPlan::Monthly;
+        membership.tier = config.trial_tier;
+        membership.mint = anchor_lang::system_program::ID;
+        membership.started_at = now;
+        membership.expires_at = expires_at;
+        membership.trial_ends_at = expires_at;
+        membership.paid_value = 0;
+        membership.paid_from = expires_at;
+        // A payment from before the trial is no longer refundable
+        membership.last_payer = Pubkey::default();
+        membership.last_amount = 0;
+        membership.bump = ctx.bumps.membership;
+        
+        emit!(TrialStarted {
+            user: ctx.accounts.user.key(),
+            tier: config.trial_tier,
+            expires_at,
+            timestamp: now,
+        });
+        
+        msg!("Membership trial started");
+        Ok(())
+    }
+
+    /// Switch an active membership to `new_plan`, paid in the mint of the latest payment.
+    /// The unused paid value is credited pro rata: the user pays the
+    /// new plan price minus the credit, or, if the credit covers it, pays nothing and
+    /// the credit is converted into time on the new plan. `max_amount` bounds the charge.
+    pub fn change_plan<'info>(
+        ctx: Context<'_, '_, '_, 'info, ChangePlan<'info>>,
+        new_plan: MembershipPlan,
+        max_amount: u64,
+    ) -> Result<()> {
+        let config = &ctx.accounts.cfg;
+        let membership = &ctx.accounts.membership;
+        let mint = membership.mint;
+        
+        // Emergency stop
+        require!(!config.paused, ErrorCode::GatewayPaused);
+        require!(!config.paused_mints.contains(&mint), ErrorCode::MintPaused);
+        
+        let now = Clock::get()?.unix_timestamp;
+        require!(membership.is_active(now), ErrorCode::MembershipExpired);
+        require!(membership.plan != new_plan, ErrorCode::SamePlan);
+        
+        let fee = token_fee(
+            config.fee(membership.tier, &mint)?,
+            ctx.accounts.price_feed.as_ref().map(|feed| feed.as_ref()),
+            &ctx.accounts.mint,
+            now,
+        )?;
+        let new_price = fee.price(new_plan);
+        
+        // Value of the unused paid time, at what was actually paid (trial time earns no credit)
+        let credit = membership.unused_value(now)?;
+        
+        // Charge the difference, or convert a surplus credit into time on the new plan
+        let (amount, duration) = if credit >= new_price {
+            let duration = prorate(new_plan.period() as u64, credit, new_price)?;
+            (0, i64::try_from(duration).map_err(|_| error!(ErrorCode::MathOverflow))?)
+        } else {
+            (new_price - credit, new_plan.period())
+        };
+        require!(amount <= max_amount, ErrorCode::PlanChangeExceedsMax);
+        
+        if amount > 0 {
+            pay_treasury(
+                &config.revenue_splits,
+                ctx.remaining_accounts,
+                &mint,
+                amount,
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.user_ata.as_ref().map(|ata| ata.to_account_info()),
+                ctx.accounts.treasury.to_account_info(),
+                ctx.accounts.treasury_ata.as_ref().map(|ata| ata.to_account_info()),
+                ctx.accounts.system_program.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.mint.to_account_info(),
+                &[],
+            )?;
+        }
+        
+        let membership = &mut ctx.accounts.membership;
+        let previous_plan = membership.plan;
+        membership.plan = new_plan;
+        membership.expires_at = now
+            .checked_add(duration)
+            .ok_or(ErrorCode::MathOverflow)?;
+        membership.paid_value = credit + amount;
+        membership.paid_from = now;
+        membership.last_amount = 0;
+        
+        emit!(PlanChanged {
+            user: ctx.accounts.user.key(),
+            mint,
+            tier: membership.tier,
+            previous_plan,
+            new_plan,
+            credit,
+            amount,
+            expires_at: membership.expires_at,
+            timestamp: now,
+        });
+        
+        msg!("Membership plan changed");
+        Ok(())
+    }
+
+    /// Refund the latest `pay_membership` payment from the refund vault to its payer
+    /// and take its period off the membership. The payer may self-refund within
+    /// `cfg.refund_window` of paying; otherwise a treasury manager must approve.
+    pub fn refund_membership(ctx: Context<RefundMembership>) -> Result<()> {
+        let config = &ctx.accounts.cfg;
+        let membership = &ctx.accounts.membership;
+        let authority = ctx.accounts.authority.key();
+        let now = Clock::get()?.unix_timestamp;
+        
+        require!(membership.last_amount > 0, ErrorCode::NothingToRefund);
+        let refund_deadline = membership.last_paid_at
+            .checked_add(config.refund_window)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            (authority == membership.last_payer && now <= refund_deadline)
+                || config.has_role(&authority, Role::TreasuryManager),
+            ErrorCode::Unauthorized
+        );
+        
+        // SOL goes back to the payer wallet, tokens to a token account it owns
+        let mint = membership.mint;
+        if mint != anchor_lang::system_program::ID {
+            let recipient_ata = ctx.accounts.recipient_ata.as_ref()
+                .ok_or(ErrorCode::InvalidTokenAccount)?;
+            let data = recipient_ata.try_borrow_data()?;
+            let state = StateWithExtensions::<SplAccount>::unpack(&data)
+                .map_err(|_| error!(ErrorCode::InvalidTokenAccount))?;
+            require_keys_eq!(state.base.owner, membership.last_payer, ErrorCode::InvalidTokenAccount);
+        }
+        
+        // The vault PDA signs for its lamports and token accounts
+        let amount = membership.last_amount;
+        let seeds: &[&[u8]] = &[b"refund_vault", &[ctx.bumps.vault]];
         transfer_asset(
             &mint,
             amount,
-            ctx.accounts.user.to_account_info(),
-            ctx.accounts.user_ata.to_account_info(),
-            ctx.accounts.treasury.to_account_info(),
-            ctx.accounts.treasury_ata.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.vault_ata.as_ref().map(|ata| ata.to_account_info()),
+            ctx.accounts.recipient.to_account_info(),
+            ctx.accounts.recipient_ata.as_ref().map(|ata| ata.to_account_info()),
             ctx.accounts.system_program.to_account_info(),
             ctx.accounts.token_program.to_account_info(),
             ctx.accounts.mint.to_account_info(),
+            &[seeds],
         )?;
         
-        // Emit event
-        emit!(MembershipPaid {
-            user: ctx.accounts.user.key(),
+        // Take the refunded period off; an active membership ends now at the latest
+        let membership = &mut ctx.accounts.membership;
+        let shortened = membership.expires_at
+            .checked_sub(membership.plan.period())
+            .ok_or(ErrorCode::MathOverflow)?;
+        membership.expires_at = shortened.max(now).min(membership.expires_at);
+        membership.paid_value = membership.paid_value.saturating_sub(amount);
+        membership.last_amount = 0;
+        
+        emit!(MembershipRefunded {
+            user: membership.user,
+            payer: membership.last_payer,
+            payment_id: membership.payments,
             mint,
             amount,
-            timestamp: Clock::get()?.unix_timestamp,
+            expires_at: membership.expires_at,
+            authority,
+            timestamp: now,
         });
         
-        msg!("Membership payment successful");
+        msg!("Membership refunded");
         Ok(())
     }
 
@@ -110,211 +385,2994 @@ pub mod transaction_gateway {
         ctx: Context<Deposit>,
         mint: Pubkey,
         amount: u64,
+        reference: Option<[u8; 32]>,
     ) -> Result<()> {
         let config = &ctx.accounts.cfg;
         
+        // Emergency stop
+        require!(!config.paused, ErrorCode::GatewayPaused);
+        require!(!config.paused_mints.contains(&mint), ErrorCode::MintPaused);
+        
         // Check if mint is allowed
         require!(
             config.allowed_mints.contains(&mint),
             ErrorCode::UnsupportedMint
         );
         
+        // The ledger is keyed by the mint account, also for SOL
+        require_keys_eq!(ctx.accounts.mint.key(), mint, ErrorCode::MintMismatch);
+        
+        // SPL deposits need the destination token account, SOL deposits no token accounts
+        let deposit_ata = if mint == anchor_lang::system_program::ID {
+            None
+        } else {
+            Some(ctx.accounts.deposit_ata.as_ref().ok_or(ErrorCode::InvalidTokenAccount)?)
+        };
+        
+        // In vault mode SPL deposits go to the vault token account of the mint
+        if config.vault_mode {
+            if let Some(deposit_ata) = deposit_ata {
+                let (vault_ata, _) = Pubkey::find_program_address(&[b"deposit_vault", mint.as_ref()], &ID);
+                require_keys_eq!(deposit_ata.key(), vault_ata, ErrorCode::InvalidVaultAccount);
+            }
+        }
+        
+        // Rolling per-user cap window over the ledger's hourly buckets
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.ledger.roll_cap_window(now);
+        let window_amount = ctx.accounts.ledger
+            .window_amount()?
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        
+        // Per-mint limits
+        require!(amount > 0, ErrorCode::DepositBelowMinimum);
+        if let Some(limits) = config.limits(&mint) {
+            require!(amount >= limits.min_amount, ErrorCode::DepositBelowMinimum);
+            require!(
+                limits.max_amount == 0 || amount <= limits.max_amount,
+                ErrorCode::DepositAboveMaximum
+            );
+            require!(
+                limits.daily_user_cap == 0 || window_amount <= limits.daily_user_cap,
+                ErrorCode::DailyDepositCapExceeded
+            );
+            // TVL caps only exist in vault mode, where the destination is pinned to the vault
+            if limits.tvl_cap > 0 {
+                let balance = match deposit_ata {
+                    Some(deposit_ata) => deposit_ata.amount,
+                    None => ctx.accounts.deposit_wallet.lamports(),
+                };
+                let tvl = balance.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+                require!(tvl <= limits.tvl_cap, ErrorCode::TvlCapExceeded);
+            }
+        }
+        
         // Transfer assets
         transfer_asset(
             &mint,
             amount,
             ctx.accounts.user.to_account_info(),
-            ctx.accounts.user_ata.to_account_info(),
+            ctx.accounts.user_ata.as_ref().map(|ata| ata.to_account_info()),
             ctx.accounts.deposit_wallet.to_account_info(),
-            ctx.accounts.deposit_ata.to_account_info(),
+            deposit_ata.map(|ata| ata.to_account_info()),
             ctx.accounts.system_program.to_account_info(),
             ctx.accounts.token_program.to_account_info(),
             ctx.accounts.mint.to_account_info(),
+            &[],
+        )?;
+        
+        // Claim the reference so the same order can't be paid twice
+        record_reference(
+            reference,
+            ctx.accounts.reference_marker.as_ref().map(|marker| marker.as_ref()),
+            &ctx.accounts.user.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            now,
         )?;
         
+        // Record the deposit in the user's ledger for this mint
+        let ledger = &mut ctx.accounts.ledger;
+        ledger.user = ctx.accounts.user.key();
+        ledger.mint = mint;
+        ledger.total_deposited = ledger.total_deposited
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ledger.deposit_count = ledger.deposit_count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ledger.last_deposit_at = now;
+        ledger.add_to_cap_window(amount)?;
+        ledger.bump = ctx.bumps.ledger;
+        
         // Emit event
         emit!(DepositEvent {
             user: ctx.accounts.user.key(),
             mint,
             amount,
-            timestamp: Clock::get()?.unix_timestamp,
+            destination: ctx.accounts.deposit_wallet.key(),
+            reference,
+            timestamp: now,
         });
         
         msg!("Deposit successful");
         Ok(())
     }
 
-    /// Update membership config (admin only)
+    /// Verify that a user holds an active membership.
+    /// Intended for CPI from other programs gating on membership.
+    pub fn verify_membership(ctx: Context<VerifyMembership>) -> Result<()> {
+        require_active_membership(
+            &ctx.accounts.membership.to_account_info(),
+            &ctx.accounts.user.key(),
+        )
+    }
+
+    /// Opt in to auto-renewal: approve the user's `AutoRenewal` PDA as delegate
+    /// of `user_ata` for up to `allowance` tokens. Calling again replaces the
+    /// token account and allowance.
+    pub fn enable_auto_renewal(
+        ctx: Context<EnableAutoRenewal>,
+        mint: Pubkey,
+        allowance: u64,
+    ) -> Result<()> {
+        // Only SPL mints can be pulled through a delegate
+        require_keys_neq!(mint, anchor_lang::system_program::ID, ErrorCode::UnsupportedMint);
+        require!(
+            ctx.accounts.cfg.fees.iter().any(|f| f.mint == mint),
+            ErrorCode::UnsupportedMint
+        );
+        
+        let cpi = Approve {
+            to: ctx.accounts.user_ata.to_account_info(),
+            delegate: ctx.accounts.auto_renewal.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        token_interface::approve(
+            CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi),
+            allowance,
+        )?;
+        
+        let now = Clock::get()?.unix_timestamp;
+        let auto_renewal = &mut ctx.accounts.auto_renewal;
+        auto_renewal.user = ctx.accounts.user.key();
+        auto_renewal.mint = mint;
+        auto_renewal.token_account = ctx.accounts.user_ata.key();
+        auto_renewal.created_at = now;
+        auto_renewal.bump = ctx.bumps.auto_renewal;
+        
+        emit!(AutoRenewalEnabled {
+            user: auto_renewal.user,
+            mint,
+            token_account: auto_renewal.token_account,
+            allowance,
+            timestamp: now,
+        });
+        
+        msg!("Auto-renewal enabled");
+        Ok(())
+    }
+
+    /// Opt out of auto-renewal: revoke the delegate and close the `AutoRenewal` PDA
+    pub fn cancel_auto_renewal(ctx: Context<CancelAutoRenewal>) -> Result<()> {
+        // A closed token account has no delegate left to revoke
+        if !ctx.accounts.user_ata.data_is_empty() {
+            let cpi = Revoke {
+                source: ctx.accounts.user_ata.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            };
+            token_interface::revoke(CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                cpi,
+            ))?;
+        }
+        
+        emit!(AutoRenewalCancelled {
+            user: ctx.accounts.user.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Auto-renewal cancelled");
+        Ok(())
+    }
+
+    /// Pull one monthly fee through the `AutoRenewal` delegate and extend the
+    /// membership (permissionless). Only allowed within `RENEWAL_WINDOW` of expiry.
+    pub fn crank_renewal<'info>(
+        ctx: Context<'_, '_, '_, 'info, CrankRenewal<'info>>,
+    ) -> Result<()> {
+        let config = &ctx.accounts.cfg;
+        let mint = ctx.accounts.auto_renewal.mint;
+        
+        // Emergency stop
+        require!(!config.paused, ErrorCode::GatewayPaused);
+        require!(!config.paused_mints.contains(&mint), ErrorCode::MintPaused);
+        
+        // Refuse early pulls
+        let now = Clock::get()?.unix_timestamp;
+        let due_at = ctx.accounts.membership.expires_at
+            .checked_sub(RENEWAL_WINDOW)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(now >= due_at, ErrorCode::RenewalNotDue);
+        
+        let amount = token_fee(
+            config.fee(ctx.accounts.membership.tier, &mint)?,
+            ctx.accounts.price_feed.as_ref().map(|feed| feed.as_ref()),
+            &ctx.accounts.mint,
+            now,
+        )?
+        .monthly_fee;
+        
+        // The AutoRenewal PDA signs as delegate of the user's token account
+        let user_key = ctx.accounts.user.key();
+        let seeds: &[&[u8]] = &[
+            b"auto_renewal",
+            user_key.as_ref(),
+            &[ctx.accounts.auto_renewal.bump],
+        ];
+        pay_treasury(
+            &config.revenue_splits,
+            ctx.remaining_accounts,
+            &mint,
+            amount,
+            ctx.accounts.auto_renewal.to_account_info(),
+            Some(ctx.accounts.user_ata.to_account_info()),
+            ctx.accounts.treasury.to_account_info(),
+            Some(ctx.accounts.treasury_ata.to_account_info()),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            &[seeds],
+        )?;
+        
+        let membership = &mut ctx.accounts.membership;
+        membership.extend(MembershipPlan::Monthly, mint, amount, now)?;
+        membership.last_amount = 0;
+        
+        emit!(MembershipRenewed {
+            user: user_key,
+            mint,
+            tier: membership.tier,
+            amount,
+            expires_at: membership.expires_at,
+            timestamp: now,
+        });
+        
+        msg!("Membership renewed");
+        Ok(())
+    }
+
+    /// Update membership config (fee manager; treasury changes also need treasury manager)
     pub fn update_membership_config(
         ctx: Context<UpdateMembershipConfig>,
-        admin: Pubkey,
         treasury: Pubkey,
         fees: Vec<MembershipFee>,
     ) -> Result<()> {
+        validate_destination(&treasury)?;
         let config = &mut ctx.accounts.config;
-        config.admin = admin;
-        config.treasury = treasury;
+        validate_fees(&fees, &config.tiers)?;
+        
+        // Only newly added mints need their mint account supplied
+        let known_mints: Vec<Pubkey> = config.fees.iter().map(|f| f.mint).collect();
+        validate_mint_accounts(fees.iter().map(|f| &f.mint), &known_mints, ctx.remaining_accounts)?;
+        
+        // Treasury changes only take effect after the timelock
+        if treasury != config.treasury && treasury != config.pending_treasury {
+            require!(
+                config.has_role(&ctx.accounts.authority.key(), Role::TreasuryManager),
+                ErrorCode::Unauthorized
+            );
+            let (key, current) = (config.key(), config.treasury);
+            let state = &mut **config;
+            queue_destination(
+                key,
+                current,
+                treasury,
+                &mut state.pending_treasury,
+                &mut state.treasury_effective_at,
+            )?;
+        }
         config.fees = fees;
         
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
         msg!("Membership config updated");
         Ok(())
     }
 
-    /// Update deposit config (admin only)
+    /// Update deposit config (fee manager; wallet changes also need treasury manager)
     pub fn update_deposit_config(
         ctx: Context<UpdateDepositConfig>,
-        admin: Pubkey,
         deposit_wallet: Pubkey,
         allowed_mints: Vec<Pubkey>,
     ) -> Result<()> {
+        validate_destination(&deposit_wallet)?;
+        validate_unique_mints(&allowed_mints)?;
         let config = &mut ctx.accounts.config;
         
-        // Calculate required space for the new allowed_mints
-        let required_space = DEPOSIT_FIXED + PUBKEY_SIZE * allowed_mints.len() + 8;
-        let current_space = config.to_account_info().data_len();
+        // Only newly added mints need their mint account supplied
+        validate_mint_accounts(allowed_mints.iter(), &config.allowed_mints, ctx.remaining_accounts)?;
         
-        // Realloc if needed
-        if required_space > current_space {
-            // Calculate rent first
-            let rent = Rent::get()?;
-            let new_minimum_balance = rent.minimum_balance(required_space);
-            let lamports_diff = new_minimum_balance.saturating_sub(config.to_account_info().lamports());
-            
-            // Transfer rent if needed (before resize)
-            if lamports_diff > 0 {
-                anchor_lang::solana_program::program::invoke(
-                    &anchor_lang::solana_program::system_instruction::transfer(
-                        &ctx.accounts.admin.key(),
-                        &config.key(),
-                        lamports_diff,
-                    ),
-                    &[
-                        ctx.accounts.admin.to_account_info(),
-                        config.to_account_info(),
-                        ctx.accounts.system_program.to_account_info(),
-                    ],
-                )?;
-            }
-            
-            // Now resize and zero-fill new bytes
-            config.to_account_info().resize(required_space)?;
+        // Deposit wallet changes only take effect after the timelock
+        if deposit_wallet != config.deposit_wallet && deposit_wallet != config.pending_deposit_wallet {
+            require!(
+                config.has_role(&ctx.accounts.authority.key(), Role::TreasuryManager),
+                ErrorCode::Unauthorized
+            );
+            let (key, current) = (config.key(), config.deposit_wallet);
+            let state = &mut **config;
+            queue_destination(
+                key,
+                current,
+                deposit_wallet,
+                &mut state.pending_deposit_wallet,
+                &mut state.deposit_wallet_effective_at,
+            )?;
         }
-        
-        config.admin = admin;
-        config.deposit_wallet = deposit_wallet;
         config.allowed_mints = allowed_mints;
         
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
         msg!("Deposit config updated");
         Ok(())
     }
 
-    /// Change admin (admin only)
-    pub fn change_membership_admin(
-        ctx: Context<ChangeMembershipAdmin>,
-        new_admin: Pubkey,
+    /// Add or update the fee for a single mint (fee manager).
+    /// With a `price_feed`, the fees are USD cents converted at payment time.
+    pub fn upsert_membership_fee(
+        ctx: Context<UpdateMembershipConfig>,
+        tier: u8,
+        mint: Pubkey,
+        monthly_fee: u64,
+        yearly_fee: u64,
+        price_feed: Option<Pubkey>,
     ) -> Result<()> {
+        validate_fee_pricing(monthly_fee, yearly_fee)?;
         let config = &mut ctx.accounts.config;
-        config.admin = new_admin;
+        require!(config.tiers.iter().any(|t| t.id == tier), ErrorCode::UnknownTier);
+        let known_mints: Vec<Pubkey> = config.fees.iter().map(|f| f.mint).collect();
+        validate_mint_accounts([&mint], &known_mints, ctx.remaining_accounts)?;
         
-        msg!("Membership admin changed");
+        let created = match config.fees.iter_mut().find(|f| f.tier == tier && f.mint == mint) {
+            Some(fee) => {
+                fee.monthly_fee = monthly_fee;
+                fee.yearly_fee = yearly_fee;
+                fee.price_feed = price_feed;
+                false
+            }
+            None => {
+                config.fees.push(MembershipFee { tier, mint, monthly_fee, yearly_fee, price_feed });
+                true
+            }
+        };
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(MembershipFeeUpserted {
+            config: config.key(),
+            tier,
+            mint,
+            monthly_fee,
+            yearly_fee,
+            price_feed,
+            created,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership fee upserted");
         Ok(())
     }
 
-    /// Change deposit admin (admin only)
-    pub fn change_deposit_admin(
-        ctx: Context<ChangeDepositAdmin>,
-        new_admin: Pubkey,
+    /// Remove the fee for a single tier and mint (fee manager)
+    pub fn remove_membership_fee(
+        ctx: Context<UpdateMembershipConfig>,
+        tier: u8,
+        mint: Pubkey,
     ) -> Result<()> {
         let config = &mut ctx.accounts.config;
-        config.admin = new_admin;
+        let index = config.fees.iter()
+            .position(|f| f.tier == tier && f.mint == mint)
+            .ok_or(ErrorCode::UnsupportedMint)?;
+        config.fees.remove(index);
         
-        msg!("Deposit admin changed");
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(MembershipFeeRemoved {
+            config: config.key(),
+            tier,
+            mint,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership fee removed");
         Ok(())
     }
-}
-/// Transfer assets (SOL or SPL tokens) to destination
-fn transfer_asset<'info>(
-    mint_key: &Pubkey,
-    amount: u64,
-    user: AccountInfo<'info>,
-    user_ata: AccountInfo<'info>,
-    treasury: AccountInfo<'info>,
-    treasury_ata: AccountInfo<'info>,
-    system_program: AccountInfo<'info>,
-    token_program: AccountInfo<'info>,
-    mint_account: AccountInfo<'info>,
-) -> Result<()> {
-    if *mint_key == anchor_lang::system_program::ID {
-        // SOL transfer
-        let cpi = anchor_lang::system_program::Transfer {
-            from: user.clone(),
-            to: treasury.clone(),
+
+    /// Add or rename a membership tier (fee manager)
+    pub fn upsert_membership_tier(
+        ctx: Context<UpdateMembershipConfig>,
+        id: u8,
+        name: String,
+    ) -> Result<()> {
+        require!(name.len() <= MAX_TIER_NAME_LEN, ErrorCode::TierNameTooLong);
+        let config = &mut ctx.accounts.config;
+        let created = match config.tiers.iter_mut().find(|t| t.id == id) {
+            Some(tier) => {
+                tier.name = name.clone();
+                false
+            }
+            None => {
+                config.tiers.push(MembershipTier { id, name: name.clone() });
+                true
+            }
         };
-        anchor_lang::system_program::transfer(
-            CpiContext::new(system_program.clone(), cpi),
-            amount,
-        )
-    } else {
-        // SPL token transfer
-        require!(mint_account.owner == &anchor_spl::token::ID, ErrorCode::UnsupportedMint);
-        require!(user_ata.owner == &anchor_spl::token::ID, ErrorCode::InvalidTokenAccount);
-        require!(treasury_ata.owner == &anchor_spl::token::ID, ErrorCode::InvalidTokenAccount);
         
-        // Use spl_token::state::Mint::unpack for better compatibility
-        let mint_data = spl_token::state::Mint::unpack(&mint_account.data.borrow())?;
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
         
-        // Additional ATA-mint validation
-        let user_ata_data = spl_token::state::Account::unpack(&user_ata.data.borrow())?;
-        let treasury_ata_data = spl_token::state::Account::unpack(&treasury_ata.data.borrow())?;
-        require!(user_ata_data.mint == *mint_key, ErrorCode::MintMismatch);
-        require!(treasury_ata_data.mint == *mint_key, ErrorCode::MintMismatch);
-        let cpi = TransferChecked {
-            from: user_ata.clone(),
-            to: treasury_ata.clone(),
-            authority: user.clone(),
-            mint: mint_account.clone(),
-        };
-        token::transfer_checked(
-            CpiContext::new(token_program.clone(), cpi),
-            amount,
-            mint_data.decimals,
-        )
+        emit!(MembershipTierUpserted {
+            config: config.key(),
+            id,
+            name,
+            created,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership tier upserted");
+        Ok(())
     }
-}
-
-/// Membership payment event
-#[event]
-pub struct MembershipPaid {
-    pub user: Pubkey,
-    pub mint: Pubkey,
-    pub amount: u64,
-    pub timestamp: i64,
-}
-
-/// Deposit event
-#[event]
-pub struct DepositEvent {
-    pub user: Pubkey,
-    pub mint: Pubkey,
-    pub amount: u64,
-    pub timestamp: i64,
-}
 
-/// Membership configuration PDA
-/// seeds = [b"membership_config"]
-#[account]
-pub struct MembershipConfig {
-    pub admin: Pubkey,
-    pub treasury: Pubkey,               // Treasury wallet for membership payments
-    pub fees: Vec<MembershipFee>,       // SOL + SPL tokens (expandable)
-}
+    /// Remove a membership tier together with its fees (fee manager).
+    /// Existing memberships keep their tier until they expire.
+    pub fn remove_membership_tier(
+        ctx: Context<UpdateMembershipConfig>,
+        id: u8,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let index = config.tiers.iter()
+            .position(|t| t.id == id)
+            .ok_or(ErrorCode::UnknownTier)?;
+        config.tiers.remove(index);
+        config.fees.retain(|f| f.tier != id);
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(MembershipTierRemoved {
+            config: config.key(),
+            id,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership tier removed");
+        Ok(())
+    }
 
-/// Mint-specific fee structure
+    /// Set the share of each membership payment routed to referrers (fee manager)
+    pub fn set_referral_bps(
+        ctx: Context<UpdateMembershipConfig>,
+        referral_bps: u16,
+    ) -> Result<()> {
+        require!(referral_bps <= 10_000, ErrorCode::InvalidReferralBps);
+        let config = &mut ctx.accounts.config;
+        config.referral_bps = referral_bps;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(ReferralBpsUpdated {
+            config: config.key(),
+            referral_bps,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Referral share updated");
+        Ok(())
+    }
+
+    /// Enable or disable free trials and set their length and tier (fee manager)
+    pub fn set_trial_config(
+        ctx: Context<UpdateMembershipConfig>,
+        enabled: bool,
+        days: u16,
+        tier: u8,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        if enabled {
+            require!(days > 0, ErrorCode::InvalidTrialDays);
+            require!(config.tiers.iter().any(|t| t.id == tier), ErrorCode::UnknownTier);
+        }
+        config.trial_enabled = enabled;
+        config.trial_days = days;
+        config.trial_tier = tier;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(TrialConfigUpdated {
+            config: config.key(),
+            enabled,
+            days,
+            tier,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Trial config updated");
+        Ok(())
+    }
+
+    /// Set how long after paying a payer may refund without approval (fee manager).
+    /// `0` leaves every refund to a treasury manager.
+    pub fn set_refund_window(
+        ctx: Context<UpdateMembershipConfig>,
+        refund_window: i64,
+    ) -> Result<()> {
+        require!(refund_window >= 0, ErrorCode::InvalidRefundWindow);
+        let config = &mut ctx.accounts.config;
+        config.refund_window = refund_window;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(RefundWindowUpdated {
+            config: config.key(),
+            refund_window,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Refund window updated");
+        Ok(())
+    }
+
+    /// Create a coupon identified by the sha256 hash of its code (fee manager).
+    /// `mint = None` makes a percent coupon valid for every mint.
+    pub fn create_coupon(
+        ctx: Context<CreateCoupon>,
+        code_hash: [u8; 32],
+        discount: Discount,
+        mint: Option<Pubkey>,
+        max_redemptions: u32,
+        expires_at: i64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        match discount {
+            Discount::Percent { bps } => require!(bps > 0 && bps <= 10_000, ErrorCode::InvalidCoupon),
+            // Fixed amounts are denominated in a single mint
+            Discount::Fixed { amount } => require!(amount > 0 && mint.is_some(), ErrorCode::InvalidCoupon),
+        }
+        require!(max_redemptions > 0 && expires_at > now, ErrorCode::InvalidCoupon);
+        
+        let coupon = &mut ctx.accounts.coupon;
+        coupon.code_hash = code_hash;
+        coupon.discount = discount;
+        coupon.mint = mint;
+        coupon.max_redemptions = max_redemptions;
+        coupon.redemptions = 0;
+        coupon.expires_at = expires_at;
+        coupon.bump = ctx.bumps.coupon;
+        
+        emit!(CouponCreated {
+            coupon: coupon.key(),
+            discount,
+            mint,
+            max_redemptions,
+            expires_at,
+            authority: ctx.accounts.authority.key(),
+            timestamp: now,
+        });
+        
+        msg!("Coupon created");
+        Ok(())
+    }
+
+    /// Close a coupon and reclaim its rent (fee manager)
+    pub fn close_coupon(ctx: Context<CloseCoupon>) -> Result<()> {
+        emit!(CouponClosed {
+            coupon: ctx.accounts.coupon.key(),
+            redemptions: ctx.accounts.coupon.redemptions,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Coupon closed");
+        Ok(())
+    }
+
+    /// Add a mint to the deposit whitelist (fee manager)
+    pub fn add_allowed_mint(
+        ctx: Context<UpdateDepositConfig>,
+        mint: Pubkey,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(!config.allowed_mints.contains(&mint), ErrorCode::DuplicateMint);
+        validate_mint_accounts([&mint], &[], ctx.remaining_accounts)?;
+        config.allowed_mints.push(mint);
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(AllowedMintAdded {
+            config: config.key(),
+            mint,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Allowed mint added");
+        Ok(())
+    }
+
+    /// Remove a mint from the deposit whitelist (fee manager)
+    pub fn remove_allowed_mint(
+        ctx: Context<UpdateDepositConfig>,
+        mint: Pubkey,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let index = config.allowed_mints.iter()
+            .position(|m| *m == mint)
+            .ok_or(ErrorCode::UnsupportedMint)?;
+        config.allowed_mints.remove(index);
+        config.deposit_limits.retain(|l| l.mint != mint);
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(AllowedMintRemoved {
+            config: config.key(),
+            mint,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Allowed mint removed");
+        Ok(())
+    }
+
+    /// Set the deposit limits of an allowed mint (fee manager).
+    /// `0` disables the maximum and either cap; all zeros removes the limits.
+    /// A TVL cap bounds the vault balance, so it needs vault mode.
+    pub fn set_deposit_limits(
+        ctx: Context<UpdateDepositConfig>,
+        mint: Pubkey,
+        min_amount: u64,
+        max_amount: u64,
+        daily_user_cap: u64,
+        tvl_cap: u64,
+    ) -> Result<()> {
+        require!(max_amount == 0 || min_amount <= max_amount, ErrorCode::InvalidDepositLimits);
+        let config = &mut ctx.accounts.config;
+        require!(config.allowed_mints.contains(&mint), ErrorCode::UnsupportedMint);
+        require!(tvl_cap == 0 || config.vault_mode, ErrorCode::TvlCapRequiresVault);
+        
+        config.deposit_limits.retain(|l| l.mint != mint);
+        if min_amount > 0 || max_amount > 0 || daily_user_cap > 0 || tvl_cap > 0 {
+            config.deposit_limits.push(DepositLimits {
+                mint,
+                min_amount,
+                max_amount,
+                daily_user_cap,
+                tvl_cap,
+            });
+        }
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(DepositLimitsUpdated {
+            config: config.key(),
+            mint,
+            min_amount,
+            max_amount,
+            daily_user_cap,
+            tvl_cap,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit limits updated");
+        Ok(())
+    }
+
+    /// Queue a treasury change behind the timelock (treasury manager). Unlike
+    /// `update_membership_config`, this needs no fee manager role and leaves the fees as they are.
+    pub fn queue_treasury_change(
+        ctx: Context<ManageMembershipTreasury>,
+        treasury: Pubkey,
+    ) -> Result<()> {
+        validate_destination(&treasury)?;
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(treasury, config.treasury, ErrorCode::InvalidDestination);
+        
+        let (key, current) = (config.key(), config.treasury);
+        let state = &mut **config;
+        queue_destination(
+            key,
+            current,
+            treasury,
+            &mut state.pending_treasury,
+            &mut state.treasury_effective_at,
+        )?;
+        
+        msg!("Membership treasury change queued");
+        Ok(())
+    }
+
+    /// Queue a deposit wallet change behind the timelock (treasury manager). Unlike
+    /// `update_deposit_config`, this needs no fee manager role and leaves the allowed mints as they are.
+    pub fn queue_deposit_wallet_change(
+        ctx: Context<ManageDepositWallet>,
+        deposit_wallet: Pubkey,
+    ) -> Result<()> {
+        validate_destination(&deposit_wallet)?;
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(deposit_wallet, config.deposit_wallet, ErrorCode::InvalidDestination);
+        
+        let (key, current) = (config.key(), config.deposit_wallet);
+        let state = &mut **config;
+        queue_destination(
+            key,
+            current,
+            deposit_wallet,
+            &mut state.pending_deposit_wallet,
+            &mut state.deposit_wallet_effective_at,
+        )?;
+        
+        msg!("Deposit wallet change queued");
+        Ok(())
+    }
+
+    /// Apply a queued treasury change once its timelock has elapsed (permissionless)
+    pub fn apply_pending_membership_config(ctx: Context<ApplyPendingMembershipConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(config.pending_treasury, Pubkey::default(), ErrorCode::NoPendingChange);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= config.treasury_effective_at, ErrorCode::TimelockNotElapsed);
+        
+        let previous = config.treasury;
+        config.treasury = config.pending_treasury;
+        config.pending_treasury = Pubkey::default();
+        config.treasury_effective_at = 0;
+        
+        emit!(DestinationChangeApplied {
+            config: config.key(),
+            previous,
+            current: config.treasury,
+            timestamp: now,
+        });
+        
+        msg!("Membership treasury updated");
+        Ok(())
+    }
+
+    /// Queue a new revenue split list (treasury manager). Takes effect after
+    /// `DESTINATION_TIMELOCK` via `apply_pending_revenue_splits`; an empty list
+    /// sends everything to the treasury again.
+    pub fn queue_revenue_splits(
+        ctx: Context<SetRevenueSplits>,
+        splits: Vec<RevenueSplit>,
+    ) -> Result<()> {
+        validate_revenue_splits(&splits)?;
+        let effective_at = Clock::get()?
+            .unix_timestamp
+            .checked_add(DESTINATION_TIMELOCK)
+            .ok_or(ErrorCode::MathOverflow)?;
+        
+        let config = &mut ctx.accounts.config;
+        config.pending_revenue_splits = Some(splits.clone());
+        config.revenue_splits_effective_at = effective_at;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(RevenueSplitsQueued {
+            config: config.key(),
+            splits,
+            effective_at,
+        });
+        
+        msg!("Revenue splits queued");
+        Ok(())
+    }
+
+    /// Apply queued revenue splits once their timelock has elapsed (permissionless)
+    pub fn apply_pending_revenue_splits(ctx: Context<ApplyPendingMembershipConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let now = Clock::get()?.unix_timestamp;
+        require!(config.pending_revenue_splits.is_some(), ErrorCode::NoPendingChange);
+        require!(now >= config.revenue_splits_effective_at, ErrorCode::TimelockNotElapsed);
+        
+        // The account only shrinks here; the slack is trimmed on the next resize
+        config.revenue_splits = config.pending_revenue_splits.take().unwrap_or_default();
+        config.revenue_splits_effective_at = 0;
+        
+        emit!(RevenueSplitsApplied {
+            config: config.key(),
+            splits: config.revenue_splits.clone(),
+            timestamp: now,
+        });
+        
+        msg!("Revenue splits updated");
+        Ok(())
+    }
+
+    /// Cancel queued revenue splits (treasury manager)
+    pub fn cancel_pending_revenue_splits(ctx: Context<ManageMembershipTreasury>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(config.pending_revenue_splits.is_some(), ErrorCode::NoPendingChange);
+        config.pending_revenue_splits = None;
+        config.revenue_splits_effective_at = 0;
+        
+        emit!(RevenueSplitsCancelled {
+            config: config.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Revenue splits change cancelled");
+        Ok(())
+    }
+
+    /// Apply a queued deposit wallet change once its timelock has elapsed (permissionless)
+    pub fn apply_pending_deposit_config(ctx: Context<ApplyPendingDepositConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(config.pending_deposit_wallet, Pubkey::default(), ErrorCode::NoPendingChange);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= config.deposit_wallet_effective_at, ErrorCode::TimelockNotElapsed);
+        
+        let previous = config.deposit_wallet;
+        config.deposit_wallet = config.pending_deposit_wallet;
+        config.pending_deposit_wallet = Pubkey::default();
+        config.deposit_wallet_effective_at = 0;
+        
+        emit!(DestinationChangeApplied {
+            config: config.key(),
+            previous,
+            current: config.deposit_wallet,
+            timestamp: now,
+        });
+        
+        msg!("Deposit wallet updated");
+        Ok(())
+    }
+
+    /// Cancel a queued treasury change (treasury manager)
+    pub fn cancel_pending_membership_config(ctx: Context<ManageMembershipTreasury>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(config.pending_treasury, Pubkey::default(), ErrorCode::NoPendingChange);
+        let cancelled = config.pending_treasury;
+        config.pending_treasury = Pubkey::default();
+        config.treasury_effective_at = 0;
+        
+        emit!(DestinationChangeCancelled {
+            config: config.key(),
+            cancelled,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership treasury change cancelled");
+        Ok(())
+    }
+
+    /// Cancel a queued deposit wallet change (treasury manager)
+    pub fn cancel_pending_deposit_config(ctx: Context<ManageDepositWallet>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(config.pending_deposit_wallet, Pubkey::default(), ErrorCode::NoPendingChange);
+        let cancelled = config.pending_deposit_wallet;
+        config.pending_deposit_wallet = Pubkey::default();
+        config.deposit_wallet_effective_at = 0;
+        
+        emit!(DestinationChangeCancelled {
+            config: config.key(),
+            cancelled,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit wallet change cancelled");
+        Ok(())
+    }
+
+    /// Route deposits to the program's deposit vault instead of `deposit_wallet`
+    /// (treasury manager). Vault funds only leave through `sweep_vault`.
+    /// Can't be disabled while a mint has a TVL cap.
+    pub fn set_deposit_vault_mode(
+        ctx: Context<ManageDepositWallet>,
+        enabled: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(
+            enabled || config.deposit_limits.iter().all(|l| l.tvl_cap == 0),
+            ErrorCode::TvlCapRequiresVault
+        );
+        config.vault_mode = enabled;
+        
+        emit!(DepositVaultModeSet {
+            config: config.key(),
+            enabled,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit vault mode updated");
+        Ok(())
+    }
+
+    /// Create the deposit vault token account of an allowed SPL mint (fee manager)
+    pub fn create_deposit_vault(ctx: Context<CreateDepositVault>) -> Result<()> {
+        msg!("Deposit vault created for {}", ctx.accounts.mint.key());
+        Ok(())
+    }
+
+    /// Move `amount` of `mint` from the deposit vault to a whitelisted sweep
+    /// destination (treasury manager). SPL sweeps go to a token account the
+    /// destination owns.
+    pub fn sweep_vault(
+        ctx: Context<SweepVault>,
+        mint: Pubkey,
+        amount: u64,
+    ) -> Result<()> {
+        let destination = ctx.accounts.destination.key();
+        if mint != anchor_lang::system_program::ID {
+            let (vault_ata, _) = Pubkey::find_program_address(&[b"deposit_vault", mint.as_ref()], &ID);
+            require_keys_eq!(ctx.accounts.vault_ata.key(), vault_ata, ErrorCode::InvalidVaultAccount);
+            
+            let data = ctx.accounts.destination_ata.try_borrow_data()?;
+            let state = StateWithExtensions::<SplAccount>::unpack(&data)
+                .map_err(|_| error!(ErrorCode::InvalidTokenAccount))?;
+            require_keys_eq!(state.base.owner, destination, ErrorCode::InvalidTokenAccount);
+        }
+        
+        // The vault PDA signs for its lamports and token accounts
+        let seeds: &[&[u8]] = &[b"deposit_vault", &[ctx.bumps.vault]];
+        transfer_asset(
+            &mint,
+            amount,
+            ctx.accounts.vault.to_account_info(),
+            Some(ctx.accounts.vault_ata.to_account_info()),
+            ctx.accounts.destination.to_account_info(),
+            Some(ctx.accounts.destination_ata.to_account_info()),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            &[seeds],
+        )?;
+        
+        emit!(VaultSwept {
+            config: ctx.accounts.config.key(),
+            mint,
+            amount,
+            destination,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit vault swept");
+        Ok(())
+    }
+
+    /// Queue a new sweep destination whitelist (treasury manager). Takes effect
+    /// after `DESTINATION_TIMELOCK` via `apply_pending_sweep_destinations`.
+    pub fn queue_sweep_destinations(
+        ctx: Context<SetSweepDestinations>,
+        destinations: Vec<Pubkey>,
+    ) -> Result<()> {
+        validate_sweep_destinations(&destinations)?;
+        let effective_at = Clock::get()?
+            .unix_timestamp
+            .checked_add(DESTINATION_TIMELOCK)
+            .ok_or(ErrorCode::MathOverflow)?;
+        
+        let config = &mut ctx.accounts.config;
+        config.pending_sweep_destinations = Some(destinations.clone());
+        config.sweep_destinations_effective_at = effective_at;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(SweepDestinationsQueued {
+            config: config.key(),
+            destinations,
+            effective_at,
+        });
+        
+        msg!("Sweep destinations queued");
+        Ok(())
+    }
+
+    /// Apply a queued sweep destination whitelist once its timelock has elapsed (permissionless)
+    pub fn apply_pending_sweep_destinations(ctx: Context<ApplyPendingDepositConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let now = Clock::get()?.unix_timestamp;
+        require!(config.pending_sweep_destinations.is_some(), ErrorCode::NoPendingChange);
+        require!(now >= config.sweep_destinations_effective_at, ErrorCode::TimelockNotElapsed);
+        
+        // The account only shrinks here; the slack is trimmed on the next resize
+        config.sweep_destinations = config.pending_sweep_destinations.take().unwrap_or_default();
+        config.sweep_destinations_effective_at = 0;
+        
+        emit!(SweepDestinationsApplied {
+            config: config.key(),
+            destinations: config.sweep_destinations.clone(),
+            timestamp: now,
+        });
+        
+        msg!("Sweep destinations updated");
+        Ok(())
+    }
+
+    /// Cancel a queued sweep destination whitelist (treasury manager)
+    pub fn cancel_pending_sweep_destinations(ctx: Context<ManageDepositWallet>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(config.pending_sweep_destinations.is_some(), ErrorCode::NoPendingChange);
+        config.pending_sweep_destinations = None;
+        config.sweep_destinations_effective_at = 0;
+        
+        emit!(SweepDestinationsCancelled {
+            config: config.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Sweep destinations change cancelled");
+        Ok(())
+    }
+
+    /// Propose a new membership admin (admin only).
+    /// Takes effect once the proposed key signs `accept_membership_admin`.
+    pub fn propose_membership_admin(
+        ctx: Context<ChangeMembershipAdmin>,
+        new_admin: Pubkey,
+    ) -> Result<()> {
+        require_keys_neq!(new_admin, Pubkey::default(), ErrorCode::InvalidAdmin);
+        let config = &mut ctx.accounts.config;
+        config.pending_admin = new_admin;
+        
+        emit!(AdminTransferProposed {
+            config: config.key(),
+            admin: config.admin,
+            pending_admin: new_admin,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership admin proposed");
+        Ok(())
+    }
+
+    /// Accept a pending membership admin proposal (pending admin only)
+    pub fn accept_membership_admin(ctx: Context<AcceptMembershipAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let previous_admin = config.admin;
+        config.admin = config.pending_admin;
+        config.pending_admin = Pubkey::default();
+        
+        emit!(AdminTransferAccepted {
+            config: config.key(),
+            previous_admin,
+            new_admin: config.admin,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership admin changed");
+        Ok(())
+    }
+
+    /// Cancel a pending membership admin proposal (admin only)
+    pub fn cancel_membership_admin_proposal(ctx: Context<ChangeMembershipAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(config.pending_admin, Pubkey::default(), ErrorCode::NoPendingAdmin);
+        let cancelled_admin = config.pending_admin;
+        config.pending_admin = Pubkey::default();
+        
+        emit!(AdminTransferCancelled {
+            config: config.key(),
+            admin: config.admin,
+            cancelled_admin,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership admin proposal cancelled");
+        Ok(())
+    }
+
+    /// Propose a new deposit admin (admin only).
+    /// Takes effect once the proposed key signs `accept_deposit_admin`.
+    pub fn propose_deposit_admin(
+        ctx: Context<ChangeDepositAdmin>,
+        new_admin: Pubkey,
+    ) -> Result<()> {
+        require_keys_neq!(new_admin, Pubkey::default(), ErrorCode::InvalidAdmin);
+        let config = &mut ctx.accounts.config;
+        config.pending_admin = new_admin;
+        
+        emit!(AdminTransferProposed {
+            config: config.key(),
+            admin: config.admin,
+            pending_admin: new_admin,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit admin proposed");
+        Ok(())
+    }
+
+    /// Accept a pending deposit admin proposal (pending admin only)
+    pub fn accept_deposit_admin(ctx: Context<AcceptDepositAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let previous_admin = config.admin;
+        config.admin = config.pending_admin;
+        config.pending_admin = Pubkey::default();
+        
+        emit!(AdminTransferAccepted {
+            config: config.key(),
+            previous_admin,
+            new_admin: config.admin,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit admin changed");
+        Ok(())
+    }
+
+    /// Cancel a pending deposit admin proposal (admin only)
+    pub fn cancel_deposit_admin_proposal(ctx: Context<ChangeDepositAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(config.pending_admin, Pubkey::default(), ErrorCode::NoPendingAdmin);
+        let cancelled_admin = config.pending_admin;
+        config.pending_admin = Pubkey::default();
+        
+        emit!(AdminTransferCancelled {
+            config: config.key(),
+            admin: config.admin,
+            cancelled_admin,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit admin proposal cancelled");
+        Ok(())
+    }
+
+    /// Grant a role on the membership config (super admin)
+    pub fn grant_membership_role(
+        ctx: Context<ManageMembershipRoles>,
+        holder: Pubkey,
+        role: Role,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        grant_role(&mut config.roles, holder, role)?;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(RoleGranted {
+            config: config.key(),
+            holder,
+            role,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership role granted");
+        Ok(())
+    }
+
+    /// Revoke a role on the membership config (super admin)
+    pub fn revoke_membership_role(
+        ctx: Context<ManageMembershipRoles>,
+        holder: Pubkey,
+        role: Role,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        revoke_role(&mut config.roles, holder, role)?;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(RoleRevoked {
+            config: config.key(),
+            holder,
+            role,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership role revoked");
+        Ok(())
+    }
+
+    /// Grant a role on the deposit config (super admin)
+    pub fn grant_deposit_role(
+        ctx: Context<ManageDepositRoles>,
+        holder: Pubkey,
+        role: Role,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        grant_role(&mut config.roles, holder, role)?;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(RoleGranted {
+            config: config.key(),
+            holder,
+            role,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit role granted");
+        Ok(())
+    }
+
+    /// Revoke a role on the deposit config (super admin)
+    pub fn revoke_deposit_role(
+        ctx: Context<ManageDepositRoles>,
+        holder: Pubkey,
+        role: Role,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        revoke_role(&mut config.roles, holder, role)?;
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(RoleRevoked {
+            config: config.key(),
+            holder,
+            role,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit role revoked");
+        Ok(())
+    }
+
+    /// Pause or resume all membership payments (pauser)
+    pub fn set_membership_paused(
+        ctx: Context<SetMembershipPaused>,
+        paused: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.paused = paused;
+        
+        emit!(ConfigPaused {
+            config: config.key(),
+            mint: None,
+            paused,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership paused: {}", paused);
+        Ok(())
+    }
+
+    /// Pause or resume all deposits (pauser)
+    pub fn set_deposit_paused(
+        ctx: Context<SetDepositPaused>,
+        paused: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.paused = paused;
+        
+        emit!(ConfigPaused {
+            config: config.key(),
+            mint: None,
+            paused,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit paused: {}", paused);
+        Ok(())
+    }
+
+    /// Pause or resume membership payments in a single mint (pauser)
+    pub fn set_membership_mint_paused(
+        ctx: Context<SetMembershipPaused>,
+        mint: Pubkey,
+        paused: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        set_mint_paused(&mut config.paused_mints, mint, paused);
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(ConfigPaused {
+            config: config.key(),
+            mint: Some(mint),
+            paused,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Membership mint paused: {}", paused);
+        Ok(())
+    }
+
+    /// Pause or resume deposits in a single mint (pauser)
+    pub fn set_deposit_mint_paused(
+        ctx: Context<SetDepositPaused>,
+        mint: Pubkey,
+        paused: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        set_mint_paused(&mut config.paused_mints, mint, paused);
+        
+        resize_config(
+            config,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(ConfigPaused {
+            config: config.key(),
+            mint: Some(mint),
+            paused,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        msg!("Deposit mint paused: {}", paused);
+        Ok(())
+    }
+}
+
+/// Whether `key` holds `role`. The config `admin` implicitly holds every role,
+/// and `SuperAdmin` grants imply all other roles.
+fn has_role(admin: &Pubkey, roles: &[RoleGrant], key: &Pubkey, role: Role) -> bool {
+    key == admin
+        || roles
+            .iter()
+            .any(|g| g.holder == *key && (g.role == role || g.role == Role::SuperAdmin))
+}
+
+/// Add a role grant, rejecting duplicates
+fn grant_role(roles: &mut Vec<RoleGrant>, holder: Pubkey, role: Role) -> Result<()> {
+    let grant = RoleGrant { holder, role };
+    require!(!roles.contains(&grant), ErrorCode::RoleAlreadyGranted);
+    roles.push(grant);
+    Ok(())
+}
+
+/// Remove a role grant
+fn revoke_role(roles: &mut Vec<RoleGrant>, holder: Pubkey, role: Role) -> Result<()> {
+    let grant = RoleGrant { holder, role };
+    let index = roles
+        .iter()
+        .position(|g| *g == grant)
+        .ok_or(ErrorCode::RoleNotFound)?;
+    roles.remove(index);
+    Ok(())
+}
+
+/// Add `mint` to or remove it from a paused-mint list
+fn set_mint_paused(paused_mints: &mut Vec<Pubkey>, mint: Pubkey, paused: bool) {
+    if paused {
+        if !paused_mints.contains(&mint) {
+            paused_mints.push(mint);
+        }
+    } else {
+        paused_mints.retain(|m| *m != mint);
+    }
+}
+
+/// Validate a membership fee list: known tiers, unique (tier, mint) pairs and sensible pricing
+fn validate_fees(fees: &[MembershipFee], tiers: &[MembershipTier]) -> Result<()> {
+    for (i, fee) in fees.iter().enumerate() {
+        validate_fee_pricing(fee.monthly_fee, fee.yearly_fee)?;
+        require!(tiers.iter().any(|t| t.id == fee.tier), ErrorCode::UnknownTier);
+        require!(
+            !fees[..i].iter().any(|f| f.tier == fee.tier && f.mint == fee.mint),
+            ErrorCode::DuplicateMint
+        );
+    }
+    Ok(())
+}
+
+/// Revenue splits must be empty or have unique, non-zero recipients summing to 10,000 bps
+fn validate_revenue_splits(splits: &[RevenueSplit]) -> Result<()> {
+    let mut total: u32 = 0;
+    for (i, split) in splits.iter().enumerate() {
+        validate_destination(&split.recipient)?;
+        require!(split.bps > 0, ErrorCode::InvalidRevenueSplits);
+        require!(
+            !splits[..i].iter().any(|s| s.recipient == split.recipient),
+            ErrorCode::InvalidRevenueSplits
+        );
+        total += split.bps as u32;
+    }
+    require!(splits.is_empty() || total == 10_000, ErrorCode::InvalidRevenueSplits);
+    Ok(())
+}
+
+/// Validate a tier list: unique ids and bounded names
+fn validate_tiers(tiers: &[MembershipTier]) -> Result<()> {
+    for (i, tier) in tiers.iter().enumerate() {
+        require!(tier.name.len() <= MAX_TIER_NAME_LEN, ErrorCode::TierNameTooLong);
+        require!(
+            !tiers[..i].iter().any(|t| t.id == tier.id),
+            ErrorCode::DuplicateTier
+        );
+    }
+    Ok(())
+}
+
+/// Fees must be non-zero and a yearly plan must cost more than a monthly one
+fn validate_fee_pricing(monthly_fee: u64, yearly_fee: u64) -> Result<()> {
+    require!(monthly_fee > 0 && yearly_fee > 0, ErrorCode::ZeroFee);
+    require!(monthly_fee < yearly_fee, ErrorCode::InvalidPlanPricing);
+    Ok(())
+}
+
+/// Reject duplicate entries in a mint list
+fn validate_unique_mints(mints: &[Pubkey]) -> Result<()> {
+    for (i, mint) in mints.iter().enumerate() {
+        require!(!mints[..i].contains(mint), ErrorCode::DuplicateMint);
+    }
+    Ok(())
+}
+
+/// Payment destinations must be set
+fn validate_destination(destination: &Pubkey) -> Result<()> {
+    require_keys_neq!(*destination, Pubkey::default(), ErrorCode::InvalidDestination);
+    Ok(())
+}
+
+/// Queue `destination` to replace `current` after `DESTINATION_TIMELOCK`,
+/// restarting the timelock if a different change was already pending.
+fn queue_destination(
+    config: Pubkey,
+    current: Pubkey,
+    destination: Pubkey,
+    pending: &mut Pubkey,
+    effective_at: &mut i64,
+) -> Result<()> {
+    if destination == *pending {
+        return Ok(());
+    }
+    let queued_at = Clock::get()?
+        .unix_timestamp
+        .checked_add(DESTINATION_TIMELOCK)
+        .ok_or(ErrorCode::MathOverflow)?;
+    *pending = destination;
+    *effective_at = queued_at;
+    
+    emit!(DestinationChangeQueued {
+        config,
+        current,
+        pending: destination,
+        effective_at: queued_at,
+    });
+    Ok(())
+}
+
+/// Sweep destinations must be set and unique
+fn validate_sweep_destinations(destinations: &[Pubkey]) -> Result<()> {
+    for (i, destination) in destinations.iter().enumerate() {
+        validate_destination(destination)?;
+        require!(!destinations[..i].contains(destination), ErrorCode::InvalidDestination);
+    }
+    Ok(())
+}
+
+/// Every SPL mint in `mints` that is not already `known` must be supplied in
+/// `mint_accounts` as an initialized legacy or Token-2022 mint. SOL is exempt.
+fn validate_mint_accounts<'a>(
+    mints: impl IntoIterator<Item = &'a Pubkey>,
+    known: &[Pubkey],
+    mint_accounts: &[AccountInfo],
+) -> Result<()> {
+    for mint in mints {
+        if *mint == anchor_lang::system_program::ID || known.contains(mint) {
+            continue;
+        }
+        let account = mint_accounts
+            .iter()
+            .find(|a| a.key == mint)
+            .ok_or(ErrorCode::InvalidMintAccount)?;
+        require!(
+            account.owner == &anchor_spl::token::ID || account.owner == &anchor_spl::token_2022::ID,
+            ErrorCode::InvalidMintAccount
+        );
+        let data = account.try_borrow_data()?;
+        let state = StateWithExtensions::<SplMint>::unpack(&data)
+            .map_err(|_| error!(ErrorCode::InvalidMintAccount))?;
+        require!(state.base.is_initialized, ErrorCode::InvalidMintAccount);
+    }
+    Ok(())
+}
+
+/// `value * part / whole`, rounded down, computed in u128
+fn prorate(value: u64, part: u64, whole: u64) -> Result<u64> {
+    require!(whole > 0, ErrorCode::MathOverflow);
+    let result = (value as u128)
+        .checked_mul(part as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / whole as u128;
+    u64::try_from(result).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// Shared body of `pay_membership` and `gift_membership`: the signer pays,
+/// `beneficiary` receives the membership.
+#[allow(clippy::too_many_arguments)]
+fn process_membership_payment<'info>(
+    ctx: Context<'_, '_, '_, 'info, PayMembership<'info>>,
+    beneficiary: Pubkey,
+    tier: u8,
+    mint: Pubkey,
+    amount: u64,
+    plan: Option<MembershipPlan>,
+    coupon_code: Option<String>,
+    reference: Option<[u8; 32]>,
+) -> Result<()> {
+    let config = &ctx.accounts.cfg;
+    
+    // Emergency stop
+    require!(!config.paused, ErrorCode::GatewayPaused);
+    require!(!config.paused_mints.contains(&mint), ErrorCode::MintPaused);
+    
+    // Find matching fee, in token units
+    let now = Clock::get()?.unix_timestamp;
+    let configured_fee = config.fee(tier, &mint)?;
+    let oracle_priced = configured_fee.price_feed.is_some();
+    let fee = token_fee(
+        configured_fee,
+        ctx.accounts.price_feed.as_ref().map(|feed| feed.as_ref()),
+        &ctx.accounts.mint,
+        now,
+    )?;
+    
+    // Redeem the coupon, if any, and discount both plan prices
+    let (monthly_fee, yearly_fee, coupon) = match &coupon_code {
+        Some(code) => {
+            let coupon = ctx.accounts.coupon.as_mut().ok_or(ErrorCode::InvalidCoupon)?;
+            coupon.redeem(code, &mint, now)?;
+            (
+                coupon.discount.apply(fee.monthly_fee)?,
+                coupon.discount.apply(fee.yearly_fee)?,
+                Some(coupon.key()),
+            )
+        }
+        None => (fee.monthly_fee, fee.yearly_fee, None),
+    };
+    
+    // Validate amount and resolve the plan being paid for
+    let (plan, amount) = match plan {
+        Some(plan) => {
+            let price = match plan {
+                MembershipPlan::Monthly => monthly_fee,
+                MembershipPlan::Yearly => yearly_fee,
+            };
+            if oracle_priced {
+                require!(price <= amount, ErrorCode::SlippageExceeded);
+            } else {
+                require!(price == amount, ErrorCode::WrongFee);
+            }
+            (plan, price)
+        }
+        None if !oracle_priced && amount == monthly_fee => (MembershipPlan::Monthly, amount),
+        None if !oracle_priced && amount == yearly_fee => (MembershipPlan::Yearly, amount),
+        None => return err!(ErrorCode::WrongFee),
+    };
+    let discount = fee.price(plan) - amount;
+    
+    // One redemption per wallet, recorded in a marker PDA
+    if let Some(coupon) = coupon {
+        let redemption = ctx.accounts.coupon_redemption.as_ref().ok_or(ErrorCode::InvalidCoupon)?;
+        let user_key = ctx.accounts.user.key();
+        let (expected, bump) = Pubkey::find_program_address(
+            &[b"coupon_redemption", coupon.as_ref(), user_key.as_ref()],
+            &ID,
+        );
+        require_keys_eq!(redemption.key(), expected, ErrorCode::InvalidCoupon);
+        require!(redemption.owner != &ID, ErrorCode::CouponAlreadyRedeemed);
+        
+        create_pda_account(
+            &ctx.accounts.user.to_account_info(),
+            &redemption.to_account_info(),
+            COUPON_REDEMPTION_SPACE,
+            &[b"coupon_redemption", coupon.as_ref(), user_key.as_ref(), &[bump]],
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        let record = CouponRedemption {
+            coupon,
+            user: user_key,
+            redeemed_at: now,
+            bump,
+        };
+        record.try_serialize(&mut &mut redemption.try_borrow_mut_data()?[..])?;
+    }
+    
+    // Claim the reference so the same order can't be paid twice
+    record_reference(
+        reference,
+        ctx.accounts.reference_marker.as_ref().map(|marker| marker.as_ref()),
+        &ctx.accounts.user.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        now,
+    )?;
+    
+    // Route the referral share, if any, to the referrer
+    let mut referral_amount = 0;
+    let referrer = match ctx.accounts.referrer.as_ref() {
+        Some(referrer) if config.referral_bps > 0 => {
+            require_keys_neq!(referrer.key(), ctx.accounts.user.key(), ErrorCode::SelfReferral);
+            require_keys_neq!(referrer.key(), beneficiary, ErrorCode::SelfReferral);
+            referral_amount = prorate(amount, config.referral_bps as u64, 10_000)?;
+            
+            // SOL goes to the wallet itself, tokens to a token account it owns
+            let referrer_ata = if mint == anchor_lang::system_program::ID {
+                None
+            } else {
+                let referrer_ata = ctx.accounts.referrer_ata.as_ref()
+                    .ok_or(ErrorCode::InvalidReferralAccount)?;
+                let data = referrer_ata.try_borrow_data()?;
+                let state = StateWithExtensions::<SplAccount>::unpack(&data)
+                    .map_err(|_| error!(ErrorCode::InvalidReferralAccount))?;
+                require_keys_eq!(state.base.owner, referrer.key(), ErrorCode::InvalidReferralAccount);
+                drop(data);
+                Some(referrer_ata.to_account_info())
+            };
+            
+            if referral_amount > 0 {
+                transfer_asset(
+                    &mint,
+                    referral_amount,
+                    ctx.accounts.user.to_account_info(),
+                    ctx.accounts.user_ata.as_ref().map(|ata| ata.to_account_info()),
+                    referrer.to_account_info(),
+                    referrer_ata,
+                    ctx.accounts.system_program.to_account_info(),
+                    ctx.accounts.token_program.to_account_info(),
+                    ctx.accounts.mint.to_account_info(),
+                    &[],
+                )?;
+            }
+            
+            record_referral(
+                ctx.accounts.referral_stats.as_ref().ok_or(ErrorCode::InvalidReferralAccount)?,
+                &ctx.accounts.user.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                &referrer.key(),
+                &mint,
+                referral_amount,
+            )?;
+            Some(referrer.key())
+        }
+        _ => None,
+    };
+    
+    // Transfer the rest to the treasury (nothing to pull for fully discounted payments)
+    let treasury_amount = amount - referral_amount;
+    if treasury_amount > 0 {
+        pay_treasury(
+            &config.revenue_splits,
+            ctx.remaining_accounts,
+            &mint,
+            treasury_amount,
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.user_ata.as_ref().map(|ata| ata.to_account_info()),
+            ctx.accounts.treasury.to_account_info(),
+            ctx.accounts.treasury_ata.as_ref().map(|ata| ata.to_account_info()),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            &[],
+        )?;
+    }
+    
+    // Extend the beneficiary's membership (renewals stack on top of the remaining period)
+    let membership_info = ctx.accounts.membership.to_account_info();
+    let (expected, bump) = Pubkey::find_program_address(&[b"membership", beneficiary.as_ref()], &ID);
+    require_keys_eq!(membership_info.key(), expected, ErrorCode::InvalidMembershipAccount);
+    let mut membership = if membership_info.owner == &ID {
+        let membership = MembershipState::try_deserialize(&mut &membership_info.try_borrow_data()?[..])?;
+        let gift = beneficiary != ctx.accounts.user.key();
+        // A running trial may be converted to any tier, but only by its owner
+        if membership.is_active(now) {
+            require!(
+                membership.tier == tier || (membership.trial_ends_at > now && !gift),
+                ErrorCode::TierMismatch
+            );
+        }
+        // Gifts must not overwrite another payer's still self-refundable payment
+        if gift && membership.last_amount > 0 && membership.last_payer != ctx.accounts.user.key() {
+            let refund_deadline = membership.last_paid_at
+                .checked_add(config.refund_window)
+                .ok_or(ErrorCode::MathOverflow)?;
+            require!(now > refund_deadline, ErrorCode::RefundPending);
+        }
+        membership
+    } else {
+        create_pda_account(
+            &ctx.accounts.user.to_account_info(),
+            &membership_info,
+            MEMBERSHIP_SPACE,
+            &[b"membership", beneficiary.as_ref(), &[bump]],
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        MembershipState {
+            user: beneficiary,
+            plan,
+            tier,
+            mint,
+            started_at: now,
+            expires_at: 0,
+            trial_ends_at: 0,
+            payments: 0,
+            last_payer: Pubkey::default(),
+            last_amount: 0,
+            last_paid_at: 0,
+            paid_value: 0,
+            paid_from: 0,
+            bump,
+        }
+    };
+    membership.tier = tier;
+    membership.extend(plan, mint, amount, now)?;
+    membership.payments = membership.payments
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+    membership.last_payer = ctx.accounts.user.key();
+    // The referral share was paid out directly, so only the treasury's part is refundable
+    membership.last_amount = treasury_amount;
+    membership.last_paid_at = now;
+    membership.try_serialize(&mut &mut membership_info.try_borrow_mut_data()?[..])?;
+    
+    // Emit event
+    emit!(MembershipPaid {
+        user: beneficiary,
+        payer: ctx.accounts.user.key(),
+        mint,
+        amount,
+        plan,
+        tier,
+        coupon,
+        discount,
+        referrer,
+        referral_amount,
+        payment_id: membership.payments,
+        reference,
+        expires_at: membership.expires_at,
+        timestamp: now,
+    });
+    
+    if beneficiary == ctx.accounts.user.key() {
+        msg!("Membership payment successful");
+    } else {
+        msg!("Membership gift successful");
+    }
+    Ok(())
+}
+
+/// Create the `PaymentReference` marker PDA of (`payer`, `reference`), failing if
+/// it already exists. Without a `marker` account the reference is not enforced.
+/// Markers are scoped per payer so nobody can claim another wallet's reference
+/// ahead of its payment; the flip side is that two wallets may pay the same
+/// reference, so merchants must check the payer of each `reference` themselves.
+fn record_reference<'info>(
+    reference: Option<[u8; 32]>,
+    marker: Option<&AccountInfo<'info>>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    now: i64,
+) -> Result<()> {
+    let (reference, marker) = match (reference, marker) {
+        (Some(reference), Some(marker)) => (reference, marker),
+        (None, Some(_)) => return err!(ErrorCode::InvalidReferenceAccount),
+        (_, None) => return Ok(()),
+    };
+    let (expected, bump) = Pubkey::find_program_address(
+        &[b"reference", payer.key.as_ref(), reference.as_ref()],
+        &ID,
+    );
+    require_keys_eq!(marker.key(), expected, ErrorCode::InvalidReferenceAccount);
+    require!(marker.owner != &ID, ErrorCode::ReferenceAlreadyUsed);
+    
+    create_pda_account(
+        payer,
+        marker,
+        PAYMENT_REFERENCE_SPACE,
+        &[b"reference", payer.key.as_ref(), reference.as_ref(), &[bump]],
+        system_program,
+    )?;
+    let record = PaymentReference {
+        reference,
+        payer: payer.key(),
+        created_at: now,
+        bump,
+    };
+    record.try_serialize(&mut &mut marker.try_borrow_mut_data()?[..])
+}
+
+/// Add one referral earning `amount` to the `ReferralStats` PDA of
+/// (`referrer`, `mint`), creating it on first use.
+fn record_referral<'info>(
+    stats: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    referrer: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let (expected, bump) = Pubkey::find_program_address(
+        &[b"referral_stats", referrer.as_ref(), mint.as_ref()],
+        &ID,
+    );
+    require_keys_eq!(stats.key(), expected, ErrorCode::InvalidReferralAccount);
+    
+    let mut record = if stats.owner == &ID {
+        ReferralStats::try_deserialize(&mut &stats.try_borrow_data()?[..])?
+    } else {
+        create_pda_account(
+            payer,
+            stats,
+            REFERRAL_STATS_SPACE,
+            &[b"referral_stats", referrer.as_ref(), mint.as_ref(), &[bump]],
+            system_program,
+        )?;
+        ReferralStats {
+            referrer: *referrer,
+            mint: *mint,
+            referrals: 0,
+            total_earned: 0,
+            bump,
+        }
+    };
+    record.referrals = record.referrals.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+    record.total_earned = record.total_earned.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+    record.try_serialize(&mut &mut stats.try_borrow_mut_data()?[..])
+}
+
+/// Create a program-owned PDA at `target`, paid by `payer`.
+/// Works even if the address was pre-funded to block `create_account`.
+fn create_pda_account<'info>(
+    payer: &AccountInfo<'info>,
+    target: &AccountInfo<'info>,
+    space: usize,
+    seeds: &[&[u8]],
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(space);
+    let current = target.lamports();
+    if current == 0 {
+        return anchor_lang::system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                anchor_lang::system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: target.clone(),
+                },
+                &[seeds],
+            ),
+            rent,
+            space as u64,
+            &ID,
+        );
+    }
+    
+    let top_up = rent.saturating_sub(current);
+    if top_up > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.clone(),
+                    to: target.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    anchor_lang::system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Allocate {
+                account_to_allocate: target.clone(),
+            },
+            &[seeds],
+        ),
+        space as u64,
+    )?;
+    anchor_lang::system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Assign {
+                account_to_assign: target.clone(),
+            },
+            &[seeds],
+        ),
+        &ID,
+    )
+}
+
+/// Resize a config account to its serialized size (including discriminator).
+/// Growing tops up rent from `payer`; shrinking refunds the excess rent to `admin`.
+fn resize_config<'info, T>(
+    config: &Account<'info, T>,
+    payer: &AccountInfo<'info>,
+    admin: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()>
+where
+    T: AccountSerialize + AccountDeserialize + Owner + Clone,
+{
+    let mut data = Vec::new();
+    config.try_serialize(&mut data)?;
+    let required_space = data.len();
+    
+    let account = config.to_account_info();
+    if required_space == account.data_len() {
+        return Ok(());
+    }
+    let minimum_balance = Rent::get()?.minimum_balance(required_space);
+    
+    if required_space > account.data_len() {
+        // Transfer rent if needed (before resize)
+        let lamports_diff = minimum_balance.saturating_sub(account.lamports());
+        if lamports_diff > 0 {
+            anchor_lang::solana_program::program::invoke(
+                &anchor_lang::solana_program::system_instruction::transfer(
+                    payer.key,
+                    account.key,
+                    lamports_diff,
+                ),
+                &[
+                    payer.clone(),
+                    account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+        account.resize(required_space)?;
+    } else {
+        // Shrink first, then hand the surplus rent back
+        account.resize(required_space)?;
+        let refund = account.lamports().saturating_sub(minimum_balance);
+        if refund > 0 {
+            **account.try_borrow_mut_lamports()? -= refund;
+            **admin.try_borrow_mut_lamports()? += refund;
+        }
+    }
+    Ok(())
+}
+
+/// Fail unless `membership` is the `MembershipState` of `user` and has not expired.
+/// Reads the account directly, so other programs can gate on membership without a CPI.
+pub fn require_active_membership(membership: &AccountInfo, user: &Pubkey) -> Result<()> {
+    // Missing or foreign accounts are reported as not found
+    if membership.owner != &ID || membership.data_is_empty() {
+        return err!(ErrorCode::MembershipNotFound);
+    }
+    let state = MembershipState::try_deserialize(&mut &membership.data.borrow()[..])
+        .map_err(|_| error!(ErrorCode::MembershipNotFound))?;
+    require_keys_eq!(state.user, *user, ErrorCode::MembershipNotFound);
+
+    require!(
+        state.is_active(Clock::get()?.unix_timestamp),
+        ErrorCode::MembershipExpired
+    );
+    Ok(())
+}
+
+/// CPI into `verify_membership`, for programs depending on this crate with the `cpi` feature.
+#[cfg(feature = "cpi")]
+pub fn verify_membership_cpi<'info>(
+    transaction_gateway_program: AccountInfo<'info>,
+    user: AccountInfo<'info>,
+    membership: AccountInfo<'info>,
+) -> Result<()> {
+    cpi::verify_membership(CpiContext::new(
+        transaction_gateway_program,
+        cpi::accounts::VerifyMembership { user, membership },
+    ))
+}
+
+/// Require `token_account` to be an SPL token account owned by `owner`
+fn validate_token_owner(token_account: Option<&AccountInfo>, owner: &Pubkey) -> Result<()> {
+    let token_account = token_account.ok_or(ErrorCode::InvalidTokenAccount)?;
+    let data = token_account.try_borrow_data()?;
+    let state = StateWithExtensions::<SplAccount>::unpack(&data)
+        .map_err(|_| error!(ErrorCode::InvalidTokenAccount))?;
+    require_keys_eq!(state.base.owner, *owner, ErrorCode::InvalidTokenAccount);
+    Ok(())
+}
+
+/// Transfer assets (SOL, SPL or Token-2022 tokens) to destination.
+/// For Token-2022 mints with a transfer fee, the sender is charged the gross
+/// amount so that the destination receives exactly `amount`.
+/// `signer_seeds` is empty unless `user` is a program PDA (e.g. a token delegate).
+/// The token accounts are only required for SPL transfers.
+#[allow(clippy::too_many_arguments)]
+fn transfer_asset<'info>(
+    mint_key: &Pubkey,
+    amount: u64,
+    user: AccountInfo<'info>,
+    user_ata: Option<AccountInfo<'info>>,
+    treasury: AccountInfo<'info>,
+    treasury_ata: Option<AccountInfo<'info>>,
+    system_program: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    mint_account: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    if *mint_key == anchor_lang::system_program::ID {
+        // SOL transfer
+        let cpi = anchor_lang::system_program::Transfer {
+            from: user.clone(),
+            to: treasury.clone(),
+        };
+        anchor_lang::system_program::transfer(
+            CpiContext::new_with_signer(system_program.clone(), cpi, signer_seeds),
+            amount,
+        )
+    } else {
+        // SPL token transfer (legacy token program or Token-2022)
+        require_keys_eq!(mint_account.key(), *mint_key, ErrorCode::MintMismatch);
+        require!(mint_account.owner == token_program.key, ErrorCode::UnsupportedMint);
+        let user_ata = user_ata.ok_or(ErrorCode::InvalidTokenAccount)?;
+        let treasury_ata = treasury_ata.ok_or(ErrorCode::InvalidTokenAccount)?;
+        require!(user_ata.owner == token_program.key, ErrorCode::InvalidTokenAccount);
+        require!(treasury_ata.owner == token_program.key, ErrorCode::InvalidTokenAccount);
+        
+        // `StateWithExtensions` also unpacks legacy (extension-less) accounts
+        let mint_data = mint_account.data.borrow();
+        let mint_state = StateWithExtensions::<SplMint>::unpack(&mint_data)?;
+        let decimals = mint_state.base.decimals;
+        
+        // Reject soulbound mints and mints whose permanent delegate could drain the destination
+        let extensions = mint_state.get_extension_types()?;
+        require!(
+            !extensions.contains(&ExtensionType::NonTransferable)
+                && !extensions.contains(&ExtensionType::PermanentDelegate),
+            ErrorCode::UnsafeMintExtension
+        );
+        
+        // Gross up so the destination receives `amount` net of the transfer fee
+        let gross_amount = match mint_state.get_extension::<TransferFeeConfig>() {
+            Ok(fee_config) => {
+                let epoch = Clock::get()?.epoch;
+                let fee = fee_config
+                    .calculate_inverse_epoch_fee(epoch, amount)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                let gross = amount.checked_add(fee).ok_or(ErrorCode::MathOverflow)?;
+                require!(
+                    fee_config.calculate_epoch_fee(epoch, gross) == Some(fee),
+                    ErrorCode::TransferFeeMismatch
+                );
+                gross
+            }
+            Err(_) => amount,
+        };
+        drop(mint_data);
+        
+        // Additional ATA-mint validation
+        let user_ata_data = user_ata.data.borrow();
+        let treasury_ata_data = treasury_ata.data.borrow();
+        let user_ata_state = StateWithExtensions::<SplAccount>::unpack(&user_ata_data)?;
+        let treasury_ata_state = StateWithExtensions::<SplAccount>::unpack(&treasury_ata_data)?;
+        require!(user_ata_state.base.mint == *mint_key, ErrorCode::MintMismatch);
+        require!(treasury_ata_state.base.mint == *mint_key, ErrorCode::MintMismatch);
+        drop(user_ata_data);
+        drop(treasury_ata_data);
+        
+        let cpi = TransferChecked {
+            from: user_ata.clone(),
+            to: treasury_ata.clone(),
+            authority: user.clone(),
+            mint: mint_account.clone(),
+        };
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program.clone(), cpi, signer_seeds),
+            gross_amount,
+            decimals,
+        )
+    }
+}
+
+/// Resolve `fee` in token units. USD-priced fees are converted with the
+/// price read from `price_feed`, which must be the fee's configured feed.
+fn token_fee(
+    fee: &MembershipFee,
+    price_feed: Option<&AccountInfo>,
+    mint_account: &AccountInfo,
+    now: i64,
+) -> Result<MembershipFee> {
+    let Some(feed) = fee.price_feed else {
+        return Ok(fee.clone());
+    };
+    let price_feed = price_feed.ok_or(ErrorCode::InvalidPriceFeed)?;
+    require_keys_eq!(price_feed.key(), feed, ErrorCode::InvalidPriceFeed);
+    let price = read_oracle_price(price_feed, now)?;
+    
+    let decimals = if fee.mint == anchor_lang::system_program::ID {
+        9
+    } else {
+        require_keys_eq!(mint_account.key(), fee.mint, ErrorCode::MintMismatch);
+        let data = mint_account.try_borrow_data()?;
+        StateWithExtensions::<SplMint>::unpack(&data)?.base.decimals
+    };
+    Ok(MembershipFee {
+        monthly_fee: usd_cents_to_tokens(fee.monthly_fee, &price, decimals)?,
+        yearly_fee: usd_cents_to_tokens(fee.yearly_fee, &price, decimals)?,
+        price_feed: None,
+        ..fee.clone()
+    })
+}
+
+/// Aggregate USD price of one whole token: `price * 10^expo`
+pub struct OraclePrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub timestamp: i64,
+}
+
+/// Pyth v2 price account markers
+const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+const PYTH_PRICE_ACCOUNT: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+
+/// Read the aggregate price from a Pyth v2 price account, rejecting prices that
+/// are not trading, from the future, older than `MAX_PRICE_AGE` or wider than
+/// `MAX_PRICE_CONF_BPS`.
+fn read_oracle_price(account: &AccountInfo, now: i64) -> Result<OraclePrice> {
+    let data = account.try_borrow_data()?;
+    require!(data.len() >= 240, ErrorCode::InvalidPriceFeed);
+    let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+    
+    require!(
+        u32_at(0) == PYTH_MAGIC && u32_at(8) == PYTH_PRICE_ACCOUNT,
+        ErrorCode::InvalidPriceFeed
+    );
+    let price = OraclePrice {
+        expo: u32_at(20) as i32,
+        timestamp: u64_at(96) as i64,
+        price: u64_at(208) as i64,
+        conf: u64_at(216),
+    };
+    require!(u32_at(224) == PYTH_STATUS_TRADING && price.price > 0, ErrorCode::InvalidPriceFeed);
+    require!(
+        price.timestamp <= now && now - price.timestamp <= MAX_PRICE_AGE,
+        ErrorCode::PriceStale
+    );
+    require!(
+        (price.conf as u128) * 10_000 <= (price.price as u128) * MAX_PRICE_CONF_BPS as u128,
+        ErrorCode::PriceUncertain
+    );
+    Ok(price)
+}
+
+/// Convert USD cents to token units at `price`, rounding up
+fn usd_cents_to_tokens(cents: u64, price: &OraclePrice, decimals: u8) -> Result<u64> {
+    // tokens = cents * 10^decimals / (100 * price * 10^expo)
+    let pow = |exp: u32| 10u128.checked_pow(exp).ok_or(ErrorCode::MathOverflow);
+    let mut numerator = (cents as u128)
+        .checked_mul(pow(decimals as u32)?)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let mut denominator = 100 * price.price as u128;
+    if price.expo < 0 {
+        numerator = numerator
+            .checked_mul(pow(price.expo.unsigned_abs())?)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        denominator = denominator
+            .checked_mul(pow(price.expo as u32)?)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    u64::try_from(numerator.div_ceil(denominator)).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// Pay `amount` to the treasury, or across `splits` when configured.
+/// `recipient_accounts` holds one account per split, in order: the recipient
+/// wallet for SOL or a token account it owns for SPL. Rounding dust goes to
+/// the treasury as primary recipient.
+#[allow(clippy::too_many_arguments)]
+fn pay_treasury<'info>(
+    splits: &[RevenueSplit],
+    recipient_accounts: &[AccountInfo<'info>],
+    mint_key: &Pubkey,
+    amount: u64,
+    user: AccountInfo<'info>,
+    user_ata: Option<AccountInfo<'info>>,
+    treasury: AccountInfo<'info>,
+    treasury_ata: Option<AccountInfo<'info>>,
+    system_program: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    mint_account: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    require!(recipient_accounts.len() >= splits.len(), ErrorCode::InvalidRecipientAccount);
+    
+    let mut distributed: u64 = 0;
+    for (split, recipient) in splits.iter().zip(recipient_accounts) {
+        let share = prorate(amount, split.bps as u64, 10_000)?;
+        if share == 0 {
+            continue;
+        }
+        if *mint_key == anchor_lang::system_program::ID {
+            require_keys_eq!(recipient.key(), split.recipient, ErrorCode::InvalidRecipientAccount);
+        } else {
+            let data = recipient.try_borrow_data()?;
+            let state = StateWithExtensions::<SplAccount>::unpack(&data)
+                .map_err(|_| error!(ErrorCode::InvalidRecipientAccount))?;
+            require_keys_eq!(state.base.owner, split.recipient, ErrorCode::InvalidRecipientAccount);
+        }
+        transfer_asset(
+            mint_key,
+            share,
+            user.clone(),
+            user_ata.clone(),
+            recipient.clone(),
+            Some(recipient.clone()),
+            system_program.clone(),
+            token_program.clone(),
+            mint_account.clone(),
+            signer_seeds,
+        )?;
+        distributed += share;
+    }
+    
+    let remainder = amount - distributed;
+    if remainder == 0 {
+        return Ok(());
+    }
+    if *mint_key != anchor_lang::system_program::ID {
+        validate_token_owner(treasury_ata.as_ref(), &treasury.key())?;
+    }
+    transfer_asset(
+        mint_key,
+        remainder,
+        user,
+        user_ata,
+        treasury,
+        treasury_ata,
+        system_program,
+        token_program,
+        mint_account,
+        signer_seeds,
+    )
+}
+
+/// Membership payment event
+#[event]
+pub struct MembershipPaid {
+    pub user: Pubkey,                   // Member credited (beneficiary of a gift)
+    pub payer: Pubkey,                  // Signer who paid
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub plan: MembershipPlan,
+    pub tier: u8,
+    pub coupon: Option<Pubkey>,         // Redeemed coupon, if any
+    pub discount: u64,                  // Amount taken off the plan price
+    pub referrer: Option<Pubkey>,       // Referrer credited, if any
+    pub referral_amount: u64,           // Part of `amount` paid to the referrer
+    pub payment_id: u64,                // Per-member payment number, referenced by refunds
+    pub reference: Option<[u8; 32]>,    // Caller-supplied order reference
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a membership payment is refunded
+#[event]
+pub struct MembershipRefunded {
+    pub user: Pubkey,
+    pub payer: Pubkey,                  // Refund recipient
+    pub payment_id: u64,                // `MembershipPaid.payment_id` of the refunded payment
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub expires_at: i64,
+    pub authority: Pubkey,              // Payer (self-refund) or approving treasury manager
+    pub timestamp: i64,
+}
+
+/// Emitted when the self-refund window changes
+#[event]
+pub struct RefundWindowUpdated {
+    pub config: Pubkey,
+    pub refund_window: i64,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when new revenue splits are queued
+#[event]
+pub struct RevenueSplitsQueued {
+    pub config: Pubkey,
+    pub splits: Vec<RevenueSplit>,
+    pub effective_at: i64,
+}
+
+/// Emitted when queued revenue splits are applied
+#[event]
+pub struct RevenueSplitsApplied {
+    pub config: Pubkey,
+    pub splits: Vec<RevenueSplit>,
+    pub timestamp: i64,
+}
+
+/// Emitted when queued revenue splits are cancelled
+#[event]
+pub struct RevenueSplitsCancelled {
+    pub config: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when the trial settings change
+#[event]
+pub struct TrialConfigUpdated {
+    pub config: Pubkey,
+    pub enabled: bool,
+    pub days: u16,
+    pub tier: u8,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a wallet starts its free trial
+#[event]
+pub struct TrialStarted {
+    pub user: Pubkey,
+    pub tier: u8,
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+/// Emitted when the referral share changes
+#[event]
+pub struct ReferralBpsUpdated {
+    pub config: Pubkey,
+    pub referral_bps: u16,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a coupon is created
+#[event]
+pub struct CouponCreated {
+    pub coupon: Pubkey,
+    pub discount: Discount,
+    pub mint: Option<Pubkey>,
+    pub max_redemptions: u32,
+    pub expires_at: i64,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a coupon is closed
+#[event]
+pub struct CouponClosed {
+    pub coupon: Pubkey,
+    pub redemptions: u32,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a member switches plan; `credit` is the prorated value of the unused time
+#[event]
+pub struct PlanChanged {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub tier: u8,
+    pub previous_plan: MembershipPlan,
+    pub new_plan: MembershipPlan,
+    pub credit: u64,
+    pub amount: u64,
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a membership is renewed by `crank_renewal`
+#[event]
+pub struct MembershipRenewed {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub tier: u8,
+    pub amount: u64,
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a user opts in to (or updates) auto-renewal
+#[event]
+pub struct AutoRenewalEnabled {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub token_account: Pubkey,
+    pub allowance: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a user opts out of auto-renewal
+#[event]
+pub struct AutoRenewalCancelled {
+    pub user: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Deposit event
+#[event]
+pub struct DepositEvent {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub destination: Pubkey,            // Deposit wallet, or the deposit vault in vault mode
+    pub reference: Option<[u8; 32]>,    // Caller-supplied order reference
+    pub timestamp: i64,
+}
+
+/// Emitted when the deposit limits of a mint change
+#[event]
+pub struct DepositLimitsUpdated {
+    pub config: Pubkey,
+    pub mint: Pubkey,
+    pub min_amount: u64,
+    pub max_amount: u64,
+    pub daily_user_cap: u64,
+    pub tvl_cap: u64,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when deposit vault mode is switched
+#[event]
+pub struct DepositVaultModeSet {
+    pub config: Pubkey,
+    pub enabled: bool,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when funds leave the deposit vault
+#[event]
+pub struct VaultSwept {
+    pub config: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub destination: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a new sweep destination whitelist is queued
+#[event]
+pub struct SweepDestinationsQueued {
+    pub config: Pubkey,
+    pub destinations: Vec<Pubkey>,
+    pub effective_at: i64,
+}
+
+/// Emitted when a queued sweep destination whitelist is applied
+#[event]
+pub struct SweepDestinationsApplied {
+    pub config: Pubkey,
+    pub destinations: Vec<Pubkey>,
+    pub timestamp: i64,
+}
+
+/// Emitted when a queued sweep destination whitelist is cancelled
+#[event]
+pub struct SweepDestinationsCancelled {
+    pub config: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a single membership fee is added (`created`) or updated
+#[event]
+pub struct MembershipFeeUpserted {
+    pub config: Pubkey,
+    pub tier: u8,
+    pub mint: Pubkey,
+    pub monthly_fee: u64,
+    pub yearly_fee: u64,
+    pub price_feed: Option<Pubkey>,
+    pub created: bool,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a single membership fee is removed
+#[event]
+pub struct MembershipFeeRemoved {
+    pub config: Pubkey,
+    pub tier: u8,
+    pub mint: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a membership tier is added (`created`) or renamed
+#[event]
+pub struct MembershipTierUpserted {
+    pub config: Pubkey,
+    pub id: u8,
+    pub name: String,
+    pub created: bool,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a membership tier and its fees are removed
+#[event]
+pub struct MembershipTierRemoved {
+    pub config: Pubkey,
+    pub id: u8,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a mint is added to the deposit whitelist
+#[event]
+pub struct AllowedMintAdded {
+    pub config: Pubkey,
+    pub mint: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a mint is removed from the deposit whitelist
+#[event]
+pub struct AllowedMintRemoved {
+    pub config: Pubkey,
+    pub mint: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a config (or a single mint within it) is paused or resumed
+#[event]
+pub struct ConfigPaused {
+    pub config: Pubkey,
+    pub mint: Option<Pubkey>,           // None = whole config
+    pub paused: bool,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when an admin transfer is proposed
+#[event]
+pub struct AdminTransferProposed {
+    pub config: Pubkey,
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when the pending admin accepts the transfer
+#[event]
+pub struct AdminTransferAccepted {
+    pub config: Pubkey,
+    pub previous_admin: Pubkey,
+    pub new_admin: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when the current admin cancels a pending transfer
+#[event]
+pub struct AdminTransferCancelled {
+    pub config: Pubkey,
+    pub admin: Pubkey,
+    pub cancelled_admin: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a treasury / deposit wallet change is queued behind the timelock
+#[event]
+pub struct DestinationChangeQueued {
+    pub config: Pubkey,
+    pub current: Pubkey,
+    pub pending: Pubkey,
+    pub effective_at: i64,
+}
+
+/// Emitted when a queued destination change is applied
+#[event]
+pub struct DestinationChangeApplied {
+    pub config: Pubkey,
+    pub previous: Pubkey,
+    pub current: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when the admin cancels a queued destination change
+#[event]
+pub struct DestinationChangeCancelled {
+    pub config: Pubkey,
+    pub cancelled: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a role is granted on a config
+#[event]
+pub struct RoleGranted {
+    pub config: Pubkey,
+    pub holder: Pubkey,
+    pub role: Role,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a role is revoked on a config
+#[event]
+pub struct RoleRevoked {
+    pub config: Pubkey,
+    pub holder: Pubkey,
+    pub role: Role,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Membership configuration PDA
+/// seeds = [b"membership_config"]
+#[account]
+pub struct MembershipConfig {
+    pub admin: Pubkey,
+    pub treasury: Pubkey,               // Treasury wallet for membership payments
+    pub tiers: Vec<MembershipTier>,     // Products on sale (e.g. Basic / Pro / Enterprise)
+    pub fees: Vec<MembershipFee>,       // Per-tier SOL + SPL token prices (expandable)
+    pub paused: bool,                   // Emergency stop for all payments
+    pub paused_mints: Vec<Pubkey>,      // Per-mint emergency stop
+    pub pending_admin: Pubkey,          // Proposed admin awaiting acceptance (default = none)
+    pub pending_treasury: Pubkey,       // Queued treasury change (default = none)
+    pub treasury_effective_at: i64,     // When `pending_treasury` can be applied
+    pub roles: Vec<RoleGrant>,          // Delegated roles (admin implicitly holds all)
+    pub referral_bps: u16,              // Share of each payment routed to the referrer
+    pub revenue_splits: Vec<RevenueSplit>,                  // Payment splits (empty = all to treasury)
+    pub pending_revenue_splits: Option<Vec<RevenueSplit>>,  // Queued splits (None = none)
+    pub revenue_splits_effective_at: i64,                   // When `pending_revenue_splits` can be applied
+    pub trial_enabled: bool,            // Whether `start_trial` is open
+    pub trial_days: u16,                // Length of a free trial
+    pub trial_tier: u8,                 // Tier granted by a free trial
+    pub refund_window: i64,             // Seconds after paying a payer may self-refund
+}
+
+impl MembershipConfig {
+    /// Whether `key` holds `role` on this config
+    pub fn has_role(&self, key: &Pubkey, role: Role) -> bool {
+        has_role(&self.admin, &self.roles, key, role)
+    }
+
+    /// Fee for paying `tier` in `mint`
+    pub fn fee(&self, tier: u8, mint: &Pubkey) -> Result<&MembershipFee> {
+        require!(self.tiers.iter().any(|t| t.id == tier), ErrorCode::UnknownTier);
+        self.fees
+            .iter()
+            .find(|f| f.tier == tier && f.mint == *mint)
+            .ok_or_else(|| error!(ErrorCode::UnsupportedMint))
+    }
+}
+
+/// Share of membership revenue paid to `recipient`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct RevenueSplit {
+    pub recipient: Pubkey,
+    pub bps: u16,
+}
+
+/// Named membership tier
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MembershipTier {
+    pub id: u8,                         // Arbitrary admin-chosen id
+    pub name: String,                   // At most MAX_TIER_NAME_LEN bytes
+}
+
+/// Tier- and mint-specific fee structure
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub struct MembershipFee {
+    pub tier: u8,
     pub mint: Pubkey, // SOL = SystemProgram::ID
-    pub monthly_fee: u64,    // lamports / token-units
+    pub monthly_fee: u64,    // lamports / token-units, or USD cents with a `price_feed`
     pub yearly_fee: u64,
+    pub price_feed: Option<Pubkey>, // Pyth-style USD price account for USD-priced fees
+}
+
+impl MembershipFee {
+    /// Price of `plan` in this mint
+    pub fn price(&self, plan: MembershipPlan) -> u64 {
+        match plan {
+            MembershipPlan::Monthly => self.monthly_fee,
+            MembershipPlan::Yearly => self.yearly_fee,
+        }
+    }
+}
+
+/// Per-user membership state PDA, readable by other programs
+/// seeds = [b"membership", user]
+#[account]
+pub struct MembershipState {
+    pub user: Pubkey,
+    pub plan: MembershipPlan,           // Plan of the latest payment
+    pub tier: u8,                       // Tier of the current membership
+    pub mint: Pubkey,                   // Mint of the latest payment
+    pub started_at: i64,                // Start of the current uninterrupted membership
+    pub expires_at: i64,
+    pub trial_ends_at: i64,             // End of the wallet's free trial (0 = never trialed)
+    pub payments: u64,                  // `pay_membership` payments made, numbering `payment_id`
+    pub last_payer: Pubkey,             // Payer of the latest payment
+    pub last_amount: u64,               // Refundable (treasury) part of the latest payment (0 = none)
+    pub last_paid_at: i64,
+    pub paid_value: u64,                // Amount paid in `mint` for the time from `paid_from` to `expires_at`
+    pub paid_from: i64,                 // Start of the paid time (trial time is unpaid)
+    pub bump: u8,
+}
+
+impl MembershipState {
+    /// Whether the membership is still valid at `now`
+    pub fn is_active(&self, now: i64) -> bool {
+        self.expires_at > now
+    }
+
+    /// Value of the paid time left at `now`: `paid_value` prorated over the
+    /// paid time, so it never exceeds what was actually paid.
+    pub fn unused_value(&self, now: i64) -> Result<u64> {
+        let paid_time = self.expires_at - self.paid_from;
+        let remaining = (self.expires_at - now.max(self.paid_from)).min(paid_time);
+        if remaining <= 0 {
+            return Ok(0);
+        }
+        prorate(self.paid_value, remaining as u64, paid_time as u64)
+    }
+
+    /// Extend by one `plan` period paid with `paid` of `mint`. Early renewals
+    /// stack on the current expiry, lapsed memberships restart from `now`.
+    /// The unused value of earlier payments in the same mint carries over.
+    pub fn extend(&mut self, plan: MembershipPlan, mint: Pubkey, paid: u64, now: i64) -> Result<()> {
+        let carried = if mint == self.mint { self.unused_value(now)? } else { 0 };
+        let base = if self.is_active(now) {
+            self.expires_at
+        } else {
+            self.started_at = now;
+            now
+        };
+        self.expires_at = base
+            .checked_add(plan.period())
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.plan = plan;
+        self.mint = mint;
+        self.paid_value = carried.checked_add(paid).ok_or(ErrorCode::MathOverflow)?;
+        self.paid_from = now.max(self.trial_ends_at);
+        Ok(())
+    }
+}
+
+/// Per-user auto-renewal PDA, approved as delegate of `token_account`
+/// seeds = [b"auto_renewal", user]
+#[account]
+pub struct AutoRenewal {
+    pub user: Pubkey,
+    pub mint: Pubkey,                   // Mint renewals are paid in
+    pub token_account: Pubkey,          // User token account the delegate pulls from
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+/// Promo coupon PDA, looked up by the sha256 hash of its code
+/// seeds = [b"coupon", code_hash]
+#[account]
+pub struct Coupon {
+    pub code_hash: [u8; 32],
+    pub discount: Discount,
+    pub mint: Option<Pubkey>,           // Only valid for this mint (None = any mint)
+    pub max_redemptions: u32,
+    pub redemptions: u32,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+impl Coupon {
+    /// Check `code` and applicability at `now`, then count one redemption
+    pub fn redeem(&mut self, code: &str, mint: &Pubkey, now: i64) -> Result<()> {
+        require!(hash(code.as_bytes()).to_bytes() == self.code_hash, ErrorCode::InvalidCoupon);
+        require!(now < self.expires_at, ErrorCode::CouponExpired);
+        require!(self.redemptions < self.max_redemptions, ErrorCode::CouponExhausted);
+        if let Some(coupon_mint) = self.mint {
+            require_keys_eq!(coupon_mint, *mint, ErrorCode::CouponNotApplicable);
+        }
+        self.redemptions += 1;
+        Ok(())
+    }
+}
+
+/// Coupon discount
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Discount {
+    Percent { bps: u16 },               // Basis points off the price
+    Fixed { amount: u64 },              // Token units off the price, floored at zero
+}
+
+impl Discount {
+    /// Discounted `price`
+    pub fn apply(&self, price: u64) -> Result<u64> {
+        match *self {
+            Discount::Percent { bps } => {
+                let off = prorate(price, bps as u64, 10_000)?;
+                Ok(price - off)
+            }
+            Discount::Fixed { amount } => Ok(price.saturating_sub(amount)),
+        }
+    }
+}
+
+/// Marks that `user` has redeemed `coupon`
+/// seeds = [b"coupon_redemption", coupon, user]
+#[account]
+pub struct CouponRedemption {
+    pub coupon: Pubkey,
+    pub user: Pubkey,
+    pub redeemed_at: i64,
+    pub bump: u8,
+}
+
+/// Running referral totals per referrer and mint
+/// seeds = [b"referral_stats", referrer, mint]
+#[account]
+pub struct ReferralStats {
+    pub referrer: Pubkey,
+    pub mint: Pubkey,
+    pub referrals: u64,                 // Referred payments
+    pub total_earned: u64,              // Total paid to the referrer in `mint`
+    pub bump: u8,
+}
+
+/// Marks a payment reference as used by a payer's deposit or membership payment
+/// seeds = [b"reference", payer, reference]
+#[account]
+pub struct PaymentReference {
+    pub reference: [u8; 32],
+    pub payer: Pubkey,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+/// Per-user deposit totals for one mint, updated by `deposit`
+/// seeds = [b"deposit_ledger", user, mint]
+#[account]
+pub struct UserDepositLedger {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub total_deposited: u64,           // Cumulative amount received by the destination
+    pub deposit_count: u64,
+    pub last_deposit_at: i64,
+    pub cap_hour: i64,                  // Hour (unix time / 3600) of the newest cap bucket
+    pub hourly_amounts: [u64; DEPOSIT_CAP_BUCKETS], // Deposits per hour, indexed by hour % DEPOSIT_CAP_BUCKETS
+    pub bump: u8,
+}
+
+impl UserDepositLedger {
+    /// Advance the cap buckets to the hour of `now`, clearing the hours that left the window
+    pub fn roll_cap_window(&mut self, now: i64) {
+        let hour = now.div_euclid(3_600);
+        if hour.saturating_sub(self.cap_hour) >= DEPOSIT_CAP_BUCKETS as i64 {
+            self.hourly_amounts = [0; DEPOSIT_CAP_BUCKETS];
+        } else {
+            for h in self.cap_hour + 1..=hour {
+                self.hourly_amounts[h.rem_euclid(DEPOSIT_CAP_BUCKETS as i64) as usize] = 0;
+            }
+        }
+        self.cap_hour = self.cap_hour.max(hour);
+    }
+
+    /// Count `amount` in the bucket of the current cap hour
+    pub fn add_to_cap_window(&mut self, amount: u64) -> Result<()> {
+        let index = self.cap_hour.rem_euclid(DEPOSIT_CAP_BUCKETS as i64) as usize;
+        self.hourly_amounts[index] = self.hourly_amounts[index]
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Deposits counted against the per-user cap
+    pub fn window_amount(&self) -> Result<u64> {
+        self.hourly_amounts
+            .iter()
+            .try_fold(0u64, |total, amount| total.checked_add(*amount))
+            .ok_or(error!(ErrorCode::MathOverflow))
+    }
+}
+
+/// Membership plan
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MembershipPlan {
+    Monthly,
+    Yearly,
+}
+
+impl MembershipPlan {
+    /// Period length in seconds
+    pub fn period(&self) -> i64 {
+        match self {
+            MembershipPlan::Monthly => MONTHLY_PERIOD,
+            MembershipPlan::Yearly => YEARLY_PERIOD,
+        }
+    }
 }
 
 /// Deposit configuration PDA
@@ -324,145 +3382,728 @@ pub struct DepositConfig {
     pub admin: Pubkey,
     pub deposit_wallet: Pubkey,         // Deposit wallet for general deposits
     pub allowed_mints: Vec<Pubkey>,     // Whitelist including SOL
+    pub paused: bool,                   // Emergency stop for all deposits
+    pub paused_mints: Vec<Pubkey>,      // Per-mint emergency stop
+    pub pending_admin: Pubkey,          // Proposed admin awaiting acceptance (default = none)
+    pub pending_deposit_wallet: Pubkey, // Queued deposit wallet change (default = none)
+    pub deposit_wallet_effective_at: i64, // When `pending_deposit_wallet` can be applied
+    pub roles: Vec<RoleGrant>,          // Delegated roles (admin implicitly holds all)
+    pub vault_mode: bool,               // Deposits go to the deposit vault instead of `deposit_wallet`
+    pub sweep_destinations: Vec<Pubkey>,                    // Wallets `sweep_vault` may send to
+    pub pending_sweep_destinations: Option<Vec<Pubkey>>,    // Queued whitelist (None = none)
+    pub sweep_destinations_effective_at: i64,               // When `pending_sweep_destinations` can be applied
+    pub deposit_limits: Vec<DepositLimits>,                 // Per-mint limits (none = unlimited)
+}
+
+impl DepositConfig {
+    /// Whether `key` holds `role` on this config
+    pub fn has_role(&self, key: &Pubkey, role: Role) -> bool {
+        has_role(&self.admin, &self.roles, key, role)
+    }
+
+    /// Deposit limits of `mint`, if any
+    pub fn limits(&self, mint: &Pubkey) -> Option<&DepositLimits> {
+        self.deposit_limits.iter().find(|l| l.mint == *mint)
+    }
+
+    /// Wallet receiving deposits: the deposit vault PDA in vault mode, else `deposit_wallet`
+    pub fn destination(&self) -> Pubkey {
+        if self.vault_mode {
+            Pubkey::find_program_address(&[b"deposit_vault"], &ID).0
+        } else {
+            self.deposit_wallet
+        }
+    }
+}
+
+/// Per-mint deposit limits (0 = no maximum / cap)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct DepositLimits {
+    pub mint: Pubkey,
+    pub min_amount: u64,                // Smallest single deposit
+    pub max_amount: u64,                // Largest single deposit
+    pub daily_user_cap: u64,            // Per-user total within any rolling 24h
+    pub tvl_cap: u64,                   // Vault balance after the deposit (vault mode only)
+}
+
+/// Config roles
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    SuperAdmin,                         // Everything below plus granting / revoking roles
+    FeeManager,                         // Fees and accepted mints
+    TreasuryManager,                    // Treasury / deposit wallet changes
+    Pauser,                             // Emergency stop
+}
+
+/// A role held by a key
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct RoleGrant {
+    pub holder: Pubkey,
+    pub role: Role,
+}
+
+#[derive(Accounts)]
+pub struct InitializeMembershipConfig<'info> {
+    /// Creates and pays rent for the singleton `MembershipConfig` PDA.
+    #[account(
+        init,
+        payer = admin,
+        space = CONFIG_SPACE,
+        seeds = [b"membership_config"],
+        bump
+    )]
+    config: Account<'info, MembershipConfig>,
+
+    /// External wallet that finally holds membership payments.
+    /// Treated as `UncheckedAccount` to support any key type (EOA, multisig).
+    /// CHECK: Validated by address constraint in PayMembership
+    #[account(mut)]
+    treasury: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    admin: Signer<'info>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeDepositConfig<'info> {
+    /// Creates and pays rent for the singleton `DepositConfig` PDA.
+    #[account(
+        init,
+        payer = admin,
+        space = CONFIG_SPACE,
+        seeds = [b"deposit_config"],
+        bump
+    )]
+    config: Account<'info, DepositConfig>,
+
+    /// External wallet for deposits.
+    /// Treated as `UncheckedAccount` to support any key type (EOA, multisig).
+    /// CHECK: Validated by address constraint in Deposit
+    #[account(mut)]
+    deposit_wallet: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    admin: Signer<'info>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayMembership<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(
+        seeds = [b"membership_config"],
+        bump
+    )]
+    cfg: Account<'info, MembershipConfig>,
+
+    /// `MembershipState` PDA of the beneficiary (the signer unless gifting),
+    /// created on first payment.
+    /// CHECK: Address and contents validated in `process_membership_payment`
+    #[account(mut)]
+    membership: UncheckedAccount<'info>,
+
+    /// Must match `cfg.treasury`.
+    /// CHECK: Validated by address constraint
+    #[account(mut, address = cfg.treasury)]
+    treasury: UncheckedAccount<'info>,
+
+    // Programs
+    // ───── programs ─────
+    system_program: Program<'info, System>,
+    /// Legacy token program or Token-2022, matching the mint owner.
+    token_program: Interface<'info, TokenInterface>,
+
+    // ───── unsafe accounts ─────
+    /// CHECK:  
+    /// For *SOL payments*, omitted (or arbitrary keys), so format validation is not required.  
+    /// For *SPL payments*, `transfer_asset()` requires it and validates actual TokenAccount existence and mint matching
+    #[account(mut)]
+    user_ata:     Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Same rationale as `user_ata`
+    #[account(mut)]
+    treasury_ata: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: mint Pubkey is passed as-is for SOL (= SystemProgram::ID) or actual SPL Mint.
+    #[account()]
+    mint: UncheckedAccount<'info>,
+
+    // ───── optional coupon accounts ─────
+    /// Coupon matching `coupon_code`.
+    #[account(mut)]
+    coupon: Option<Account<'info, Coupon>>,
+
+    /// Per-wallet redemption marker, created by the handler.
+    /// CHECK: Address and emptiness validated in `pay_membership`
+    #[account(mut)]
+    coupon_redemption: Option<UncheckedAccount<'info>>,
+
+    // ───── optional referral accounts ─────
+    /// Referrer wallet; receives SOL referral shares directly.
+    /// CHECK: Any key other than the payer
+    #[account(mut)]
+    referrer: Option<UncheckedAccount<'info>>,
+
+    /// Referrer token account for SPL payments.
+    /// CHECK: Ownership validated in `pay_membership`, mint in `transfer_asset`
+    #[account(mut)]
+    referrer_ata: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: `ReferralStats` PDA of (referrer, mint), validated and created in `record_referral`
+    #[account(mut)]
+    referral_stats: Option<UncheckedAccount<'info>>,
+
+    /// Price account of a USD-priced fee.
+    /// CHECK: Must match the fee's `price_feed`, parsed in `read_oracle_price`
+    price_feed: Option<UncheckedAccount<'info>>,
+
+    /// Marker enforcing a one-time `reference` per payer, created by the handler.
+    /// CHECK: Address and emptiness validated in `record_reference`
+    #[account(mut)]
+    reference_marker: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct StartTrial<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(
+        seeds = [b"membership_config"],
+        bump
+    )]
+    cfg: Account<'info, MembershipConfig>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = MEMBERSHIP_SPACE,
+        seeds = [b"membership", user.key().as_ref()],
+        bump
+    )]
+    membership: Account<'info, MembershipState>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ChangePlan<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(
+        seeds = [b"membership_config"],
+        bump
+    )]
+    cfg: Account<'info, MembershipConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"membership", user.key().as_ref()],
+        bump = membership.bump
+    )]
+    membership: Account<'info, MembershipState>,
+
+    /// Must match `cfg.treasury`.
+    /// CHECK: Validated by address constraint
+    #[account(mut, address = cfg.treasury)]
+    treasury: UncheckedAccount<'info>,
+
+    system_program: Program<'info, System>,
+    token_program: Interface<'info, TokenInterface>,
+
+    /// CHECK: Same rationale as `PayMembership::user_ata`
+    #[account(mut)]
+    user_ata: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Same rationale as `PayMembership::user_ata`
+    #[account(mut)]
+    treasury_ata: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Address pinned to the mint of the latest payment
+    #[account(address = membership.mint)]
+    mint: UncheckedAccount<'info>,
+
+    /// Price account of a USD-priced fee.
+    /// CHECK: Must match the fee's `price_feed`, parsed in `read_oracle_price`
+    price_feed: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct RefundMembership<'info> {
+    /// Latest payer within the refund window, or a treasury manager
+    authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"membership_config"],
+        bump
+    )]
+    cfg: Account<'info, MembershipConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"membership", membership.user.as_ref()],
+        bump = membership.bump
+    )]
+    membership: Account<'info, MembershipState>,
+
+    /// Lamport vault and token account authority funding refunds, topped up by the treasury.
+    /// CHECK: PDA validated by seeds; holds no data
+    #[account(
+        mut,
+        seeds = [b"refund_vault"],
+        bump
+    )]
+    vault: UncheckedAccount<'info>,
+
+    /// Payer of the refunded payment.
+    /// CHECK: Validated by address constraint
+    #[account(mut, address = membership.last_payer)]
+    recipient: UncheckedAccount<'info>,
+
+    system_program: Program<'info, System>,
+    token_program: Interface<'info, TokenInterface>,
+
+    /// Vault token account (authority = `vault`) for SPL refunds.
+    /// CHECK: Same rationale as `PayMembership::user_ata`
+    #[account(mut)]
+    vault_ata: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Same rationale as `PayMembership::user_ata`; owner validated in `refund_membership`
+    #[account(mut)]
+    recipient_ata: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Address pinned to the mint of the latest payment
+    #[account(address = membership.mint)]
+    mint: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(
+        seeds = [b"deposit_config"],
+        bump
+    )]
+    cfg: Account<'info, DepositConfig>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = DEPOSIT_LEDGER_SPACE,
+        seeds = [b"deposit_ledger", user.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    ledger: Account<'info, UserDepositLedger>,
+
+    /// Must match `cfg.destination()`: the deposit vault in vault mode, else `cfg.deposit_wallet`.
+    /// CHECK: Validated by address constraint
+    #[account(mut, address = cfg.destination())]
+    deposit_wallet: UncheckedAccount<'info>,
+
+    // Programs
+    system_program: Program<'info, System>,
+    /// Legacy token program or Token-2022, matching the mint owner.
+    token_program: Interface<'info, TokenInterface>,
+
+    // SPL accounts (omitted for SOL deposits)
+    #[account(mut)]
+    user_ata: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Must be owned by `deposit_wallet`, so the ledger and event see the real destination.
+    #[account(mut, token::authority = deposit_wallet)]
+    deposit_ata: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: Validated in transfer_asset function and against the `mint` argument
+    #[account()]
+    mint: UncheckedAccount<'info>,
+
+    /// Marker enforcing a one-time `reference` per payer, created by the handler.
+    /// CHECK: Address and emptiness validated in `record_reference`
+    #[account(mut)]
+    reference_marker: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDepositVault<'info> {
+    #[account(
+        seeds = [b"deposit_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::FeeManager) @ ErrorCode::Unauthorized,
+        constraint = config.allowed_mints.contains(&mint.key()) @ ErrorCode::UnsupportedMint
+    )]
+    config: Account<'info, DepositConfig>,
+
+    /// Authority of every vault token account.
+    /// CHECK: PDA validated by seeds; holds no data
+    #[account(seeds = [b"deposit_vault"], bump)]
+    vault: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"deposit_vault", mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = vault,
+        token::token_program = token_program
+    )]
+    vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    authority: Signer<'info>,
+    system_program: Program<'info, System>,
+    token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct InitializeMembershipConfig<'info> {
-    /// Creates and pays rent for the singleton `MembershipConfig` PDA.
+pub struct SweepVault<'info> {
     #[account(
-        init,
-        payer = admin,
-        space = CONFIG_SPACE,
-        seeds = [b"membership_config"],
+        seeds = [b"deposit_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::TreasuryManager) @ ErrorCode::Unauthorized
+    )]
+    config: Account<'info, DepositConfig>,
+    authority: Signer<'info>,
+
+    /// Lamport vault and authority of the vault token accounts.
+    /// CHECK: PDA validated by seeds; holds no data
+    #[account(
+        mut,
+        seeds = [b"deposit_vault"],
         bump
     )]
-    config: Account<'info, MembershipConfig>,
+    vault: UncheckedAccount<'info>,
 
-    /// External wallet that finally holds membership payments.
-    /// Treated as `UncheckedAccount` to support any key type (EOA, multisig).
-    /// CHECK: Validated by address constraint in PayMembership
+    /// Must be in `config.sweep_destinations`.
+    /// CHECK: Validated by constraint
+    #[account(
+        mut,
+        constraint = config.sweep_destinations.contains(&destination.key()) @ ErrorCode::InvalidDestination
+    )]
+    destination: UncheckedAccount<'info>,
+
+    system_program: Program<'info, System>,
+    token_program: Interface<'info, TokenInterface>,
+
+    /// Vault token account of `mint` (seeds: "deposit_vault", mint) for SPL sweeps.
+    /// CHECK: Address validated in `sweep_vault`
     #[account(mut)]
-    treasury: UncheckedAccount<'info>,
+    vault_ata: UncheckedAccount<'info>,
 
+    /// CHECK: Same rationale as `PayMembership::user_ata`; owner validated in `sweep_vault`
     #[account(mut)]
-    admin: Signer<'info>,
+    destination_ata: UncheckedAccount<'info>,
 
-    system_program: Program<'info, System>,
+    /// CHECK: Validated in transfer_asset function
+    #[account()]
+    mint: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct InitializeDepositConfig<'info> {
-    /// Creates and pays rent for the singleton `DepositConfig` PDA.
+pub struct VerifyMembership<'info> {
+    /// CHECK: Only used to derive the membership PDA
+    user: UncheckedAccount<'info>,
+
+    /// May be uninitialized; reported as `MembershipNotFound` by the handler.
+    /// CHECK: Address enforced by seeds, contents validated in `require_active_membership`
     #[account(
-        init,
-        payer = admin,
-        space = CONFIG_SPACE,
-        seeds = [b"deposit_config"],
+        seeds = [b"membership", user.key().as_ref()],
         bump
     )]
-    config: Account<'info, DepositConfig>,
+    membership: UncheckedAccount<'info>,
+}
 
-    /// External wallet for deposits.
-    /// Treated as `UncheckedAccount` to support any key type (EOA, multisig).
-    /// CHECK: Validated by address constraint in Deposit
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct EnableAutoRenewal<'info> {
     #[account(mut)]
-    deposit_wallet: UncheckedAccount<'info>,
+    user: Signer<'info>,
 
-    #[account(mut)]
-    admin: Signer<'info>,
+    #[account(
+        seeds = [b"membership_config"],
+        bump
+    )]
+    cfg: Account<'info, MembershipConfig>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = AUTO_RENEWAL_SPACE,
+        seeds = [b"auto_renewal", user.key().as_ref()],
+        bump
+    )]
+    auto_renewal: Account<'info, AutoRenewal>,
+
+    #[account(
+        mut,
+        constraint = user_ata.mint == mint @ ErrorCode::MintMismatch,
+        constraint = user_ata.owner == user.key() @ ErrorCode::InvalidTokenAccount
+    )]
+    user_ata: InterfaceAccount<'info, TokenAccount>,
 
     system_program: Program<'info, System>,
+    token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct PayMembership<'info> {
+pub struct CancelAutoRenewal<'info> {
     #[account(mut)]
     user: Signer<'info>,
 
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"auto_renewal", user.key().as_ref()],
+        bump = auto_renewal.bump
+    )]
+    auto_renewal: Account<'info, AutoRenewal>,
+
+    /// CHECK: Address pinned to the stored token account; may already be closed
+    #[account(mut, address = auto_renewal.token_account)]
+    user_ata: UncheckedAccount<'info>,
+
+    token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CrankRenewal<'info> {
+    /// CHECK: Only used to derive the membership and auto-renewal PDAs
+    user: UncheckedAccount<'info>,
+
     #[account(
         seeds = [b"membership_config"],
         bump
     )]
     cfg: Account<'info, MembershipConfig>,
 
+    #[account(
+        mut,
+        seeds = [b"membership", user.key().as_ref()],
+        bump = membership.bump
+    )]
+    membership: Account<'info, MembershipState>,
+
+    #[account(
+        seeds = [b"auto_renewal", user.key().as_ref()],
+        bump = auto_renewal.bump
+    )]
+    auto_renewal: Account<'info, AutoRenewal>,
+
     /// Must match `cfg.treasury`.
     /// CHECK: Validated by address constraint
     #[account(mut, address = cfg.treasury)]
     treasury: UncheckedAccount<'info>,
 
-    // Programs
-    // ───── programs ─────
     system_program: Program<'info, System>,
-    #[account(address = anchor_spl::token::ID)]
-    token_program: Program<'info, Token>,
+    token_program: Interface<'info, TokenInterface>,
 
-    // ───── unsafe accounts ─────
-    /// CHECK:  
-    /// For *SOL payments*, arbitrary (non-existent) keys can be passed, so format validation is not required.  
-    /// For *SPL payments*, `transfer_asset()` internally validates actual TokenAccount existence and mint matching
-    #[account(mut)]
-    user_ata:     UncheckedAccount<'info>,
+    /// CHECK: Address pinned to the approved token account, contents validated in `transfer_asset`
+    #[account(mut, address = auto_renewal.token_account)]
+    user_ata: UncheckedAccount<'info>,
 
-    /// CHECK: Same rationale as `user_ata`
+    /// CHECK: Owner (`cfg.treasury`) validated in `pay_treasury`, mint in `transfer_asset`
     #[account(mut)]
     treasury_ata: UncheckedAccount<'info>,
 
-    /// CHECK: mint Pubkey is passed as-is for SOL (= SystemProgram::ID) or actual SPL Mint.
-    #[account()]
+    /// CHECK: Address pinned to the renewal mint
+    #[account(address = auto_renewal.mint)]
     mint: UncheckedAccount<'info>,
 
+    /// Price account of a USD-priced fee.
+    /// CHECK: Must match the fee's `price_feed`, parsed in `read_oracle_price`
+    price_feed: Option<UncheckedAccount<'info>>,
 }
 
 #[derive(Accounts)]
-pub struct Deposit<'info> {
+pub struct UpdateMembershipConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"membership_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::FeeManager) @ ErrorCode::Unauthorized,
+        has_one = admin
+    )]
+    config: Account<'info, MembershipConfig>,
     #[account(mut)]
-    user: Signer<'info>,
+    authority: Signer<'info>,
+    system_program: Program<'info, System>,
+
+    /// Receives the surplus rent when the config shrinks.
+    /// CHECK: Must match `config.admin`
+    #[account(mut)]
+    admin: UncheckedAccount<'info>,
+}
 
+#[derive(Accounts)]
+#[instruction(code_hash: [u8; 32])]
+pub struct CreateCoupon<'info> {
     #[account(
-        seeds = [b"deposit_config"],
+        seeds = [b"membership_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::FeeManager) @ ErrorCode::Unauthorized
+    )]
+    config: Account<'info, MembershipConfig>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = COUPON_SPACE,
+        seeds = [b"coupon", code_hash.as_ref()],
         bump
     )]
-    cfg: Account<'info, DepositConfig>,
+    coupon: Account<'info, Coupon>,
 
-    /// Must match `cfg.deposit_wallet`.
-    /// CHECK: Validated by address constraint
-    #[account(mut, address = cfg.deposit_wallet)]
-    deposit_wallet: UncheckedAccount<'info>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    system_program: Program<'info, System>,
+}
 
-    // Programs
+#[derive(Accounts)]
+pub struct CloseCoupon<'info> {
+    #[account(
+        seeds = [b"membership_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::FeeManager) @ ErrorCode::Unauthorized
+    )]
+    config: Account<'info, MembershipConfig>,
+
+    #[account(mut, close = authority)]
+    coupon: Account<'info, Coupon>,
+
+    #[account(mut)]
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDepositConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"deposit_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::FeeManager) @ ErrorCode::Unauthorized,
+        has_one = admin
+    )]
+    config: Account<'info, DepositConfig>,
+    #[account(mut)]
+    authority: Signer<'info>,
     system_program: Program<'info, System>,
-    #[account(address = anchor_spl::token::ID)]
-    token_program: Program<'info, Token>,
 
-    // SPL accounts (dummy for SOL deposits)
+    /// Receives the surplus rent when the config shrinks.
+    /// CHECK: Must match `config.admin`
     #[account(mut)]
-    user_ata: Account<'info, TokenAccount>,
+    admin: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageMembershipTreasury<'info> {
+    #[account(
+        mut,
+        seeds = [b"membership_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::TreasuryManager) @ ErrorCode::Unauthorized
+    )]
+    config: Account<'info, MembershipConfig>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRevenueSplits<'info> {
+    #[account(
+        mut,
+        seeds = [b"membership_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::TreasuryManager) @ ErrorCode::Unauthorized,
+        has_one = admin
+    )]
+    config: Account<'info, MembershipConfig>,
     #[account(mut)]
-    deposit_ata: Account<'info, TokenAccount>,
-    /// CHECK: Validated in transfer_asset function
-    #[account()]
-    mint: UncheckedAccount<'info>,
+    authority: Signer<'info>,
+    system_program: Program<'info, System>,
+
+    /// Receives the surplus rent when the config shrinks.
+    /// CHECK: Must match `config.admin`
+    #[account(mut)]
+    admin: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateMembershipConfig<'info> {
-    #[account(mut, seeds = [b"membership_config"], bump, has_one = admin)]
+pub struct SetSweepDestinations<'info> {
+    #[account(
+        mut,
+        seeds = [b"deposit_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::TreasuryManager) @ ErrorCode::Unauthorized,
+        has_one = admin
+    )]
+    config: Account<'info, DepositConfig>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    system_program: Program<'info, System>,
+
+    /// Receives the surplus rent when the config shrinks.
+    /// CHECK: Must match `config.admin`
+    #[account(mut)]
+    admin: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageDepositWallet<'info> {
+    #[account(
+        mut,
+        seeds = [b"deposit_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::TreasuryManager) @ ErrorCode::Unauthorized
+    )]
+    config: Account<'info, DepositConfig>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageMembershipRoles<'info> {
+    #[account(
+        mut,
+        seeds = [b"membership_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::SuperAdmin) @ ErrorCode::Unauthorized,
+        has_one = admin
+    )]
     config: Account<'info, MembershipConfig>,
-    admin: Signer<'info>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    system_program: Program<'info, System>,
+
+    /// Receives the surplus rent when the config shrinks.
+    /// CHECK: Must match `config.admin`
+    #[account(mut)]
+    admin: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateDepositConfig<'info> {
+pub struct ManageDepositRoles<'info> {
     #[account(
         mut,
         seeds = [b"deposit_config"],
         bump,
+        constraint = config.has_role(&authority.key(), Role::SuperAdmin) @ ErrorCode::Unauthorized,
         has_one = admin
     )]
     config: Account<'info, DepositConfig>,
     #[account(mut)]
-    admin: Signer<'info>,
+    authority: Signer<'info>,
     system_program: Program<'info, System>,
+
+    /// Receives the surplus rent when the config shrinks.
+    /// CHECK: Must match `config.admin`
+    #[account(mut)]
+    admin: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
@@ -479,6 +4120,84 @@ pub struct ChangeDepositAdmin<'info> {
     admin: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct ApplyPendingMembershipConfig<'info> {
+    #[account(mut, seeds = [b"membership_config"], bump)]
+    config: Account<'info, MembershipConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ApplyPendingDepositConfig<'info> {
+    #[account(mut, seeds = [b"deposit_config"], bump)]
+    config: Account<'info, DepositConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptMembershipAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [b"membership_config"],
+        bump,
+        constraint = config.pending_admin != Pubkey::default() @ ErrorCode::NoPendingAdmin,
+        constraint = config.pending_admin == new_admin.key() @ ErrorCode::Unauthorized
+    )]
+    config: Account<'info, MembershipConfig>,
+    new_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptDepositAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [b"deposit_config"],
+        bump,
+        constraint = config.pending_admin != Pubkey::default() @ ErrorCode::NoPendingAdmin,
+        constraint = config.pending_admin == new_admin.key() @ ErrorCode::Unauthorized
+    )]
+    config: Account<'info, DepositConfig>,
+    new_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMembershipPaused<'info> {
+    #[account(
+        mut,
+        seeds = [b"membership_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::Pauser) @ ErrorCode::Unauthorized,
+        has_one = admin
+    )]
+    config: Account<'info, MembershipConfig>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    system_program: Program<'info, System>,
+
+    /// Receives the surplus rent when the config shrinks.
+    /// CHECK: Must match `config.admin`
+    #[account(mut)]
+    admin: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetDepositPaused<'info> {
+    #[account(
+        mut,
+        seeds = [b"deposit_config"],
+        bump,
+        constraint = config.has_role(&authority.key(), Role::Pauser) @ ErrorCode::Unauthorized,
+        has_one = admin
+    )]
+    config: Account<'info, DepositConfig>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    system_program: Program<'info, System>,
+
+    /// Receives the surplus rent when the config shrinks.
+    /// CHECK: Must match `config.admin`
+    #[account(mut)]
+    admin: UncheckedAccount<'info>,
+}
+
 #[error_code]
 pub enum ErrorCode {
     #[msg("Unsupported mint")]
@@ -489,5 +4208,121 @@ pub enum ErrorCode {
     InvalidTokenAccount,
     #[msg("Mint mismatch")]
     MintMismatch,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Membership not found")]
+    MembershipNotFound,
+    #[msg("Membership expired")]
+    MembershipExpired,
+    #[msg("Mint has an unsupported extension (non-transferable or permanent delegate)")]
+    UnsafeMintExtension,
+    #[msg("Transfer fee prevents the destination from receiving the exact amount")]
+    TransferFeeMismatch,
+    #[msg("Gateway is paused")]
+    GatewayPaused,
+    #[msg("Mint is paused")]
+    MintPaused,
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Invalid admin")]
+    InvalidAdmin,
+    #[msg("No pending admin")]
+    NoPendingAdmin,
+    #[msg("No pending change")]
+    NoPendingChange,
+    #[msg("Timelock has not elapsed")]
+    TimelockNotElapsed,
+    #[msg("Role already granted")]
+    RoleAlreadyGranted,
+    #[msg("Role not found")]
+    RoleNotFound,
+    #[msg("Duplicate mint")]
+    DuplicateMint,
+    #[msg("Fee must be greater than zero")]
+    ZeroFee,
+    #[msg("Yearly fee must be greater than monthly fee")]
+    InvalidPlanPricing,
+    #[msg("Invalid destination")]
+    InvalidDestination,
+    #[msg("Mint account missing or not an SPL mint")]
+    InvalidMintAccount,
+    #[msg("Membership is not due for renewal yet")]
+    RenewalNotDue,
+    #[msg("Membership is already on this plan")]
+    SamePlan,
+    #[msg("Plan change costs more than the allowed maximum")]
+    PlanChangeExceedsMax,
+    #[msg("Unknown membership tier")]
+    UnknownTier,
+    #[msg("Duplicate membership tier")]
+    DuplicateTier,
+    #[msg("Tier name too long")]
+    TierNameTooLong,
+    #[msg("Active membership is on a different tier")]
+    TierMismatch,
+    #[msg("Invalid coupon")]
+    InvalidCoupon,
+    #[msg("Coupon expired")]
+    CouponExpired,
+    #[msg("Coupon has no redemptions left")]
+    CouponExhausted,
+    #[msg("Coupon does not apply to this mint")]
+    CouponNotApplicable,
+    #[msg("Coupon already redeemed by this wallet")]
+    CouponAlreadyRedeemed,
+    #[msg("Cannot refer yourself")]
+    SelfReferral,
+    #[msg("Referral share must not exceed 10,000 bps")]
+    InvalidReferralBps,
+    #[msg("Missing or invalid referral account")]
+    InvalidReferralAccount,
+    #[msg("Revenue splits must have unique recipients and sum to 10,000 bps")]
+    InvalidRevenueSplits,
+    #[msg("Missing or invalid revenue recipient account")]
+    InvalidRecipientAccount,
+    #[msg("Missing or invalid price feed")]
+    InvalidPriceFeed,
+    #[msg("Oracle price is stale")]
+    PriceStale,
+    #[msg("Oracle price confidence interval too wide")]
+    PriceUncertain,
+    #[msg("Price exceeds the maximum amount")]
+    SlippageExceeded,
+    #[msg("Invalid beneficiary")]
+    InvalidBeneficiary,
+    #[msg("Membership account does not match the member")]
+    InvalidMembershipAccount,
+    #[msg("Free trials are disabled")]
+    TrialsDisabled,
+    #[msg("Trial length must be greater than zero")]
+    InvalidTrialDays,
+    #[msg("Free trial already used by this wallet")]
+    TrialAlreadyUsed,
+    #[msg("Membership is already active")]
+    MembershipActive,
+    #[msg("No refundable payment")]
+    NothingToRefund,
+    #[msg("Refund window must not be negative")]
+    InvalidRefundWindow,
+    #[msg("Token account is not the deposit vault of this mint")]
+    InvalidVaultAccount,
+    #[msg("Deposit below the minimum")]
+    DepositBelowMinimum,
+    #[msg("Deposit above the maximum")]
+    DepositAboveMaximum,
+    #[msg("Daily deposit cap exceeded")]
+    DailyDepositCapExceeded,
+    #[msg("Deposit cap (TVL) exceeded")]
+    TvlCapExceeded,
+    #[msg("Minimum deposit exceeds the maximum")]
+    InvalidDepositLimits,
+    #[msg("Reference marker missing or invalid")]
+    InvalidReferenceAccount,
+    #[msg("Reference already used")]
+    ReferenceAlreadyUsed,
+    #[msg("Another payer's payment is still refundable")]
+    RefundPending,
+    #[msg("TVL caps require vault mode")]
+    TvlCapRequiresVault,
 }
 
@@ -0,0 +1,159 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { BankrunProvider, startAnchor } from "anchor-bankrun";
+import { Clock, ProgramTestContext } from "solana-bankrun";
+import { TransactionGateway } from "../target/types/transaction_gateway";
+import {
+  PublicKey,
+  SystemProgram,
+  Keypair,
+  LAMPORTS_PER_SOL,
+  Transaction,
+} from "@solana/web3.js";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { expect } from "chai";
+
+const IDL = require("../target/idl/transaction_gateway.json");
+
+// The per-user deposit cap rolls over time, which bankrun can fast-forward
+describe("transaction-gateway deposit cap", () => {
+  let context: ProgramTestContext;
+  let program: Program<TransactionGateway>;
+
+  const admin = Keypair.generate();
+  const depositWallet = Keypair.generate();
+  const depositor = Keypair.generate();
+
+  let depositConfigPda: PublicKey;
+
+  const DAILY_CAP = 15_000;
+  const HOUR = 3_600;
+
+  // Funds `pubkey` with a system account holding `sol`
+  function fund(pubkey: PublicKey, sol: number) {
+    context.setAccount(pubkey, {
+      lamports: sol * LAMPORTS_PER_SOL,
+      data: Buffer.alloc(0),
+      owner: SystemProgram.programId,
+      executable: false,
+    });
+  }
+
+  // Moves the bank clock to `unixTimestamp`
+  async function setTime(unixTimestamp: bigint) {
+    const clock = await context.banksClient.getClock();
+    context.setClock(
+      new Clock(
+        clock.slot,
+        clock.epochStartTimestamp,
+        clock.epoch,
+        clock.leaderScheduleEpoch,
+        unixTimestamp
+      )
+    );
+  }
+
+  // Processes `tx` and asserts it fails with the given error code
+  async function expectError(tx: Promise<Transaction>, code: string) {
+    const transaction = await tx;
+    transaction.recentBlockhash = context.lastBlockhash;
+    transaction.feePayer = depositor.publicKey;
+    transaction.sign(depositor);
+    const result = await context.banksClient.tryProcessTransaction(
+      transaction
+    );
+    expect(result.result).to.not.be.null;
+    expect(result.meta.logMessages.join("\n")).to.include(code);
+  }
+
+  const deposit = (amount: number) =>
+    program.methods
+      .deposit(SystemProgram.programId, new anchor.BN(amount), null)
+      .accounts({
+        user: depositor.publicKey,
+        cfg: depositConfigPda,
+        depositWallet: depositWallet.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        userAta: null,
+        depositAta: null,
+        mint: SystemProgram.programId,
+      })
+      .signers([depositor]);
+
+  before(async () => {
+    context = await startAnchor(".", [], []);
+    const provider = new BankrunProvider(context);
+    program = new Program<TransactionGateway>(IDL, provider);
+
+    fund(admin.publicKey, 10);
+    fund(depositWallet.publicKey, 1);
+    fund(depositor.publicKey, 1);
+
+    [depositConfigPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("deposit_config")],
+      program.programId
+    );
+
+    await program.methods
+      .initializeDepositConfig(admin.publicKey, depositWallet.publicKey, [
+        SystemProgram.programId,
+      ])
+      .accounts({
+        config: depositConfigPda,
+        depositWallet: depositWallet.publicKey,
+        admin: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+    await program.methods
+      .setDepositLimits(
+        SystemProgram.programId,
+        new anchor.BN(0),
+        new anchor.BN(0),
+        new anchor.BN(DAILY_CAP),
+        new anchor.BN(0)
+      )
+      .accounts({
+        config: depositConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Caps deposits per user over a rolling 24h window", async () => {
+    const start = (await context.banksClient.getClock()).unixTimestamp;
+    const at = (hours: number) => start + BigInt(hours * HOUR);
+
+    await deposit(6_000).rpc();
+    await setTime(at(23));
+    await deposit(9_000).rpc();
+    await expectError(deposit(1).transaction(), "DailyDepositCapExceeded");
+
+    // A fixed window would reset here; the 23h-old deposit still counts
+    await setTime(at(25));
+    await expectError(deposit(6_001).transaction(), "DailyDepositCapExceeded");
+    await deposit(5_999).rpc();
+    await expectError(deposit(2).transaction(), "DailyDepositCapExceeded");
+
+    // Once every deposit left the window the full cap is available again
+    await setTime(at(50));
+    await deposit(DAILY_CAP).rpc();
+
+    const [ledgerPda] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("deposit_ledger"),
+        depositor.publicKey.toBuffer(),
+        SystemProgram.programId.toBuffer(),
+      ],
+      program.programId
+    );
+    const ledger = await program.account.userDepositLedger.fetch(ledgerPda);
+    expect(ledger.totalDeposited.toNumber()).to.equal(35_999);
+    expect(ledger.depositCount.toNumber()).to.equal(4);
+  });
+});
@@ -0,0 +1,213 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { BankrunProvider, startAnchor } from "anchor-bankrun";
+import { ProgramTestContext } from "solana-bankrun";
+import { TransactionGateway } from "../target/types/transaction_gateway";
+import {
+  PublicKey,
+  SystemProgram,
+  Keypair,
+  LAMPORTS_PER_SOL,
+  Transaction,
+} from "@solana/web3.js";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { expect } from "chai";
+
+const IDL = require("../target/idl/transaction_gateway.json");
+
+// Oracle-priced fees run in bankrun, which can write raw Pyth price accounts
+// relative to the bank clock without deploying a mock oracle program
+describe("transaction-gateway oracle", () => {
+  let context: ProgramTestContext;
+  let program: Program<TransactionGateway>;
+
+  const admin = Keypair.generate();
+  const treasury = Keypair.generate();
+  const member = Keypair.generate();
+
+  let membershipConfigPda: PublicKey;
+
+  const USD_TIER = 11;
+  const MONTHLY_CENTS = new anchor.BN(1_000); // $10
+  const YEARLY_CENTS = new anchor.BN(10_000); // $100
+  // $10 at $150/SOL, rounded up
+  const MONTHLY_LAMPORTS = 66_666_667;
+
+  // Pyth v2 price account fields read by `read_oracle_price`
+  const PRICE_ACCOUNT_SPACE = 240;
+  const PYTH_MAGIC = 0xa1b2c3d4;
+  const PYTH_VERSION = 2;
+  const PYTH_PRICE_ACCOUNT = 3;
+  const PYTH_STATUS_TRADING = 1;
+
+  // Funds `pubkey` with a system account holding `sol`
+  function fund(pubkey: PublicKey, sol: number) {
+    context.setAccount(pubkey, {
+      lamports: sol * LAMPORTS_PER_SOL,
+      data: Buffer.alloc(0),
+      owner: SystemProgram.programId,
+      executable: false,
+    });
+  }
+
+  // Writes a trading SOL/USD price account at $150 (expo -8), published
+  // `age` seconds before the bank clock (negative = in the future)
+  async function createPriceFeed(conf: number, age = 0): Promise<PublicKey> {
+    const feed = Keypair.generate().publicKey;
+    const clock = await context.banksClient.getClock();
+    const data = Buffer.alloc(PRICE_ACCOUNT_SPACE);
+    data.writeUInt32LE(PYTH_MAGIC, 0);
+    data.writeUInt32LE(PYTH_VERSION, 4);
+    data.writeUInt32LE(PYTH_PRICE_ACCOUNT, 8);
+    data.writeUInt32LE(PRICE_ACCOUNT_SPACE, 12);
+    data.writeInt32LE(-8, 20);
+    data.writeBigInt64LE(clock.unixTimestamp - BigInt(age), 96);
+    data.writeBigInt64LE(BigInt(15_000_000_000), 208);
+    data.writeBigUInt64LE(BigInt(conf), 216);
+    data.writeUInt32LE(PYTH_STATUS_TRADING, 224);
+    context.setAccount(feed, {
+      lamports: LAMPORTS_PER_SOL,
+      data,
+      owner: SystemProgram.programId,
+      executable: false,
+    });
+    return feed;
+  }
+
+  // Processes `tx` and asserts it fails with the given error code
+  async function expectError(tx: Promise<Transaction>, code: string) {
+    const transaction = await tx;
+    transaction.recentBlockhash = context.lastBlockhash;
+    transaction.feePayer = member.publicKey;
+    transaction.sign(member);
+    const result = await context.banksClient.tryProcessTransaction(
+      transaction
+    );
+    expect(result.result).to.not.be.null;
+    expect(result.meta.logMessages.join("\n")).to.include(code);
+  }
+
+  const adminAccounts = () => ({
+    config: membershipConfigPda,
+    authority: admin.publicKey,
+    systemProgram: SystemProgram.programId,
+  });
+
+  const setFeed = (feed: PublicKey) =>
+    program.methods
+      .upsertMembershipFee(
+        USD_TIER,
+        SystemProgram.programId,
+        MONTHLY_CENTS,
+        YEARLY_CENTS,
+        feed
+      )
+      .accounts(adminAccounts())
+      .signers([admin])
+      .rpc();
+
+  const payUsd = (maxAmount: number, feed: PublicKey) =>
+    program.methods
+      .payMembership(
+        USD_TIER,
+        SystemProgram.programId,
+        new anchor.BN(maxAmount),
+        { monthly: {} },
+        null,
+        null
+      )
+      .accounts({
+        user: member.publicKey,
+        cfg: membershipConfigPda,
+        membership: PublicKey.findProgramAddressSync(
+          [Buffer.from("membership"), member.publicKey.toBuffer()],
+          program.programId
+        )[0],
+        treasury: treasury.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        userAta: null,
+        treasuryAta: null,
+        mint: SystemProgram.programId,
+        priceFeed: feed,
+      })
+      .signers([member]);
+
+  before(async () => {
+    context = await startAnchor(".", [], []);
+    const provider = new BankrunProvider(context);
+    program = new Program<TransactionGateway>(IDL, provider);
+
+    fund(admin.publicKey, 10);
+    fund(treasury.publicKey, 1);
+    fund(member.publicKey, 2);
+
+    [membershipConfigPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership_config")],
+      program.programId
+    );
+
+    await program.methods
+      .initializeMembershipConfig(
+        admin.publicKey,
+        treasury.publicKey,
+        [{ id: USD_TIER, name: "Usd" }],
+        []
+      )
+      .accounts({
+        config: membershipConfigPda,
+        treasury: treasury.publicKey,
+        admin: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Converts USD-priced fees with the oracle price", async () => {
+    const solUsdFeed = await createPriceFeed(5_000_000);
+    await setFeed(solUsdFeed);
+
+    await expectError(
+      payUsd(60_000_000, solUsdFeed).transaction(),
+      "SlippageExceeded"
+    );
+    const otherFeed = await createPriceFeed(5_000_000);
+    await expectError(
+      payUsd(70_000_000, otherFeed).transaction(),
+      "InvalidPriceFeed"
+    );
+
+    // Only the exact converted amount is charged, not the maximum
+    const treasuryBefore = await context.banksClient.getBalance(
+      treasury.publicKey
+    );
+    await payUsd(70_000_000, solUsdFeed).rpc();
+    expect(
+      Number(
+        (await context.banksClient.getBalance(treasury.publicKey)) -
+          treasuryBefore
+      )
+    ).to.equal(MONTHLY_LAMPORTS);
+
+    // Stale, future-dated and low-confidence prices are rejected
+    const staleFeed = await createPriceFeed(5_000_000, 3_600);
+    await setFeed(staleFeed);
+    await expectError(
+      payUsd(70_000_000, staleFeed).transaction(),
+      "PriceStale"
+    );
+    const futureFeed = await createPriceFeed(5_000_000, -3_600);
+    await setFeed(futureFeed);
+    await expectError(
+      payUsd(70_000_000, futureFeed).transaction(),
+      "PriceStale"
+    );
+    const wideFeed = await createPriceFeed(1_500_000_000);
+    await setFeed(wideFeed);
+    await expectError(
+      payUsd(70_000_000, wideFeed).transaction(),
+      "PriceUncertain"
+    );
+  });
+});
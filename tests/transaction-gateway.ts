@@ -6,14 +6,25 @@ import {
   SystemProgram,
   Keypair,
   LAMPORTS_PER_SOL,
+  Transaction,
+  TransactionInstruction,
+  sendAndConfirmTransaction,
 } from "@solana/web3.js";
 import {
   TOKEN_PROGRAM_ID,
+  TOKEN_2022_PROGRAM_ID,
+  ExtensionType,
   createMint,
   createAccount,
+  getAccount,
   mintTo,
+  getMintLen,
+  createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  createInitializeNonTransferableMintInstruction,
 } from "@solana/spl-token";
 import { expect } from "chai";
+import { createHash } from "crypto";
 
 describe("transaction-gateway", () => {
   const provider = anchor.AnchorProvider.env();
@@ -31,6 +42,7 @@ describe("transaction-gateway", () => {
   // PDAs
   let membershipConfigPda: PublicKey;
   let depositConfigPda: PublicKey;
+  let membershipPda: PublicKey;
 
   // SPL Token for testing
   let testMint: PublicKey;
@@ -38,6 +50,15 @@ describe("transaction-gateway", () => {
   let treasuryAta: PublicKey;
   let depositAta: PublicKey;
 
+  // Membership periods (must match the program constants)
+  const MONTHLY_PERIOD = 30 * 86_400;
+  const YEARLY_PERIOD = 365 * 86_400;
+  const MEMBERSHIP_SPACE =
+    8 + 32 + 1 + 1 + 32 + 8 + 8 + 8 + 8 + 32 + 8 + 8 + 8 + 8 + 1;
+  const DEPOSIT_LEDGER_SPACE = 8 + 32 + 32 + 8 + 8 + 8 + 8 + 8 * 25 + 1;
+  const DESTINATION_TIMELOCK = 48 * 3_600;
+  const RENEWAL_WINDOW = 86_400;
+
   // Helper functions
   const toLamports = (sol: number) =>
     new anchor.BN(Math.round(sol * LAMPORTS_PER_SOL));
@@ -50,6 +71,65 @@ describe("transaction-gateway", () => {
     await provider.connection.confirmTransaction(sig, "confirmed");
   }
 
+  // Awaits `call` and asserts it fails with the given error code
+  async function expectError(call: Promise<unknown>, code: string) {
+    try {
+      await call;
+      expect.fail("Should have thrown an error");
+    } catch (error) {
+      expect(error.toString()).to.include(code);
+    }
+  }
+
+  // Creates `count` legacy SPL mints (6 decimals, admin authority)
+  async function createMints(count: number): Promise<PublicKey[]> {
+    const mints: PublicKey[] = [];
+    for (let i = 0; i < count; i++) {
+      mints.push(
+        await createMint(provider.connection, admin, admin.publicKey, null, 6)
+      );
+    }
+    return mints;
+  }
+
+  // Mint accounts passed as remaining accounts when registering new mints
+  const mintMetas = (mints: PublicKey[]) =>
+    mints.map((pubkey) => ({ pubkey, isSigner: false, isWritable: false }));
+
+  // Creates a Token-2022 mint (6 decimals, admin authority) with the given extensions
+  async function createToken2022Mint(
+    extensions: ExtensionType[],
+    extensionIxs: (mint: PublicKey) => TransactionInstruction[]
+  ): Promise<PublicKey> {
+    const mintKeypair = Keypair.generate();
+    const mintLen = getMintLen(extensions);
+    const lamports =
+      await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+
+    const tx = new Transaction().add(
+      SystemProgram.createAccount({
+        fromPubkey: admin.publicKey,
+        newAccountPubkey: mintKeypair.publicKey,
+        space: mintLen,
+        lamports,
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      ...extensionIxs(mintKeypair.publicKey),
+      createInitializeMintInstruction(
+        mintKeypair.publicKey,
+        6,
+        admin.publicKey,
+        null,
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    await sendAndConfirmTransaction(provider.connection, tx, [
+      admin,
+      mintKeypair,
+    ]);
+    return mintKeypair.publicKey;
+  }
+
   before(async () => {
     // Airdrop SOL to test accounts
     await airdrop(admin.publicKey, 10);
@@ -66,6 +146,10 @@ describe("transaction-gateway", () => {
       [Buffer.from("deposit_config")],
       program.programId
     );
+    [membershipPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), user.publicKey.toBuffer()],
+      program.programId
+    );
 
     // Create test SPL token
     testMint = await createMint(
@@ -106,27 +190,38 @@ describe("transaction-gateway", () => {
     const MONTHLY_TOKEN = new anchor.BN(100_000); // 100k tokens
     const YEARLY_TOKEN = new anchor.BN(1_000_000); // 1M tokens
 
+    const tiers = [{ id: 0, name: "Basic" }];
     const fees = [
       {
+        tier: 0,
         mint: SystemProgram.programId,
         monthlyFee: MONTHLY_SOL,
         yearlyFee: YEARLY_SOL,
+        priceFeed: null,
       },
       {
+        tier: 0,
         mint: testMint,
         monthlyFee: MONTHLY_TOKEN,
         yearlyFee: YEARLY_TOKEN,
+        priceFeed: null,
       },
     ];
 
     await program.methods
-      .initializeMembershipConfig(admin.publicKey, treasury.publicKey, fees)
+      .initializeMembershipConfig(
+        admin.publicKey,
+        treasury.publicKey,
+        tiers,
+        fees
+      )
       .accounts({
         config: membershipConfigPda,
         treasury: treasury.publicKey,
         admin: admin.publicKey,
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(mintMetas([testMint]))
       .signers([admin])
       .rpc();
 
@@ -135,6 +230,7 @@ describe("transaction-gateway", () => {
     );
     expect(config.admin.toString()).to.equal(admin.publicKey.toString());
     expect(config.treasury.toString()).to.equal(treasury.publicKey.toString());
+    expect(config.tiers.length).to.equal(1);
     expect(config.fees.length).to.equal(2);
   });
 
@@ -153,6 +249,7 @@ describe("transaction-gateway", () => {
         admin: admin.publicKey,
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(mintMetas([testMint]))
       .signers([admin])
       .rpc();
 
@@ -174,10 +271,11 @@ describe("transaction-gateway", () => {
     );
 
     const tx = await program.methods
-      .payMembership(SystemProgram.programId, MONTHLY_SOL)
+      .payMembership(0, SystemProgram.programId, MONTHLY_SOL, null, null, null)
       .accounts({
         user: user.publicKey,
         cfg: membershipConfigPda,
+        membership: membershipPda,
         treasury: treasury.publicKey,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -212,14 +310,31 @@ describe("transaction-gateway", () => {
       treasury.publicKey
     );
 
-    // User balance should be exactly decreased by transfer amount (no fee in local validator)
+    // User pays the fee plus rent for the new membership state (no fee in local validator)
+    const membershipRent =
+      await provider.connection.getMinimumBalanceForRentExemption(
+        MEMBERSHIP_SPACE
+      );
     expect(userBalanceAfter).to.equal(
-      userBalanceBefore - MONTHLY_SOL.toNumber()
+      userBalanceBefore - MONTHLY_SOL.toNumber() - membershipRent
     );
     // Treasury balance should be exactly increased by transfer amount
     expect(treasuryBalanceAfter).to.equal(
       treasuryBalanceBefore + MONTHLY_SOL.toNumber()
     );
+
+    // Membership state should be created with a 30-day expiry
+    const membership = await program.account.membershipState.fetch(
+      membershipPda
+    );
+    expect(membership.user.toString()).to.equal(user.publicKey.toString());
+    expect(membership.plan).to.deep.equal({ monthly: {} });
+    expect(membership.mint.toString()).to.equal(
+      SystemProgram.programId.toString()
+    );
+    expect(
+      membership.expiresAt.toNumber() - membership.startedAt.toNumber()
+    ).to.equal(MONTHLY_PERIOD);
   });
 
   it("Pays membership SPL token", async () => {
@@ -231,10 +346,11 @@ describe("transaction-gateway", () => {
       await provider.connection.getTokenAccountBalance(treasuryAta);
 
     const tx = await program.methods
-      .payMembership(testMint, MONTHLY_TOKEN)
+      .payMembership(0, testMint, MONTHLY_TOKEN, null, null, null)
       .accounts({
         user: user.publicKey,
         cfg: membershipConfigPda,
+        membership: membershipPda,
         treasury: treasury.publicKey,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -264,6 +380,260 @@ describe("transaction-gateway", () => {
     expect(treasuryAfter).to.equal(treasuryBefore + MONTHLY_TOKEN.toNumber());
   });
 
+  it("Rejects a treasury token account not owned by the treasury", async () => {
+    // Paying into the payer's own token account must not buy a membership
+    await expectError(
+      program.methods
+        .payMembership(0, testMint, new anchor.BN(100_000), null, null, null)
+        .accounts({
+          user: user.publicKey,
+          cfg: membershipConfigPda,
+          membership: membershipPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: userAta,
+          mint: testMint,
+        })
+        .signers([user])
+        .rpc(),
+      "InvalidTokenAccount"
+    );
+  });
+
+  it("Stacks early renewals on the current expiry", async () => {
+    const YEARLY_SOL = toLamports(1.0);
+    const before = await program.account.membershipState.fetch(membershipPda);
+
+    await program.methods
+      .payMembership(0, SystemProgram.programId, YEARLY_SOL, null, null, null)
+      .accounts({
+        user: user.publicKey,
+        cfg: membershipConfigPda,
+        membership: membershipPda,
+        treasury: treasury.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        userAta: userAta,
+        treasuryAta: treasuryAta,
+        mint: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const after = await program.account.membershipState.fetch(membershipPda);
+    expect(after.plan).to.deep.equal({ yearly: {} });
+    expect(after.startedAt.toNumber()).to.equal(before.startedAt.toNumber());
+    expect(after.expiresAt.toNumber()).to.equal(
+      before.expiresAt.toNumber() + YEARLY_PERIOD
+    );
+  });
+
+  it("Verifies an active membership", async () => {
+    await program.methods
+      .verifyMembership()
+      .accounts({
+        user: user.publicKey,
+        membership: membershipPda,
+      })
+      .rpc();
+  });
+
+  it("Rejects membership verification for non-members", async () => {
+    const stranger = Keypair.generate().publicKey;
+    const [strangerMembership] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), stranger.toBuffer()],
+      program.programId
+    );
+
+    await expectError(
+      program.methods
+        .verifyMembership()
+        .accounts({
+          user: stranger,
+          membership: strangerMembership,
+        })
+        .rpc(),
+      "MembershipNotFound"
+    );
+  });
+
+  it("Pays membership with a Token-2022 transfer-fee mint net of fees", async () => {
+    const MONTHLY_TOKEN = new anchor.BN(100_000);
+    const feeMint = await createToken2022Mint(
+      [ExtensionType.TransferFeeConfig],
+      (mint) => [
+        createInitializeTransferFeeConfigInstruction(
+          mint,
+          admin.publicKey,
+          admin.publicKey,
+          100, // 1%
+          BigInt(1_000_000),
+          TOKEN_2022_PROGRAM_ID
+        ),
+      ]
+    );
+    const userFeeAta = await createAccount(
+      provider.connection,
+      admin,
+      feeMint,
+      user.publicKey,
+      undefined,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const treasuryFeeAta = await createAccount(
+      provider.connection,
+      admin,
+      feeMint,
+      treasury.publicKey,
+      undefined,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(
+      provider.connection,
+      admin,
+      feeMint,
+      userFeeAta,
+      admin,
+      1_000_000,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    // Register the new mint in the membership fee list
+    const config = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    await program.methods
+      .updateMembershipConfig(treasury.publicKey, [
+        ...config.fees,
+        {
+          tier: 0,
+          mint: feeMint,
+          monthlyFee: MONTHLY_TOKEN,
+          yearlyFee: new anchor.BN(1_000_000),
+          priceFeed: null,
+        },
+      ])
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(mintMetas([feeMint]))
+      .signers([admin])
+      .rpc();
+
+    await program.methods
+      .payMembership(0, feeMint, MONTHLY_TOKEN, null, null, null)
+      .accounts({
+        user: user.publicKey,
+        cfg: membershipConfigPda,
+        membership: membershipPda,
+        treasury: treasury.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        userAta: userFeeAta,
+        treasuryAta: treasuryFeeAta,
+        mint: feeMint,
+      })
+      .signers([user])
+      .rpc();
+
+    const userAfter = await provider.connection.getTokenAccountBalance(
+      userFeeAta
+    );
+    const treasuryAfter = await provider.connection.getTokenAccountBalance(
+      treasuryFeeAta
+    );
+
+    // Treasury receives exactly the configured fee; the user also covers the 1% transfer fee
+    expect(parseInt(treasuryAfter.value.amount)).to.equal(
+      MONTHLY_TOKEN.toNumber()
+    );
+    expect(1_000_000 - parseInt(userAfter.value.amount)).to.be.greaterThan(
+      MONTHLY_TOKEN.toNumber()
+    );
+  });
+
+  it("Rejects deposits of non-transferable Token-2022 mints", async () => {
+    const soulboundMint = await createToken2022Mint(
+      [ExtensionType.NonTransferable],
+      (mint) => [
+        createInitializeNonTransferableMintInstruction(
+          mint,
+          TOKEN_2022_PROGRAM_ID
+        ),
+      ]
+    );
+    const userSoulboundAta = await createAccount(
+      provider.connection,
+      admin,
+      soulboundMint,
+      user.publicKey,
+      undefined,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const depositSoulboundAta = await createAccount(
+      provider.connection,
+      admin,
+      soulboundMint,
+      depositWallet.publicKey,
+      undefined,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(
+      provider.connection,
+      admin,
+      soulboundMint,
+      userSoulboundAta,
+      admin,
+      1_000_000,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const config = await program.account.depositConfig.fetch(depositConfigPda);
+    await program.methods
+      .updateDepositConfig(depositWallet.publicKey, [
+        ...config.allowedMints,
+        soulboundMint,
+      ])
+      .accounts({
+        config: depositConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(mintMetas([soulboundMint]))
+      .signers([admin])
+      .rpc();
+
+    await expectError(
+      program.methods
+        .deposit(soulboundMint, new anchor.BN(1_000), null)
+        .accounts({
+          user: user.publicKey,
+          cfg: depositConfigPda,
+          depositWallet: depositWallet.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          userAta: userSoulboundAta,
+          depositAta: depositSoulboundAta,
+          mint: soulboundMint,
+        })
+        .signers([user])
+        .rpc(),
+      "UnsafeMintExtension"
+    );
+  });
+
   it("Makes SOL deposit", async () => {
     const DEPOSIT_SOL = toLamports(0.05);
     const userBalanceBefore = await provider.connection.getBalance(
@@ -286,7 +656,7 @@ describe("transaction-gateway", () => {
       console.log("Deposit config PDA:", depositConfigPda.toString());
 
       const tx = await program.methods
-        .deposit(SystemProgram.programId, DEPOSIT_SOL)
+        .deposit(SystemProgram.programId, DEPOSIT_SOL, null)
         .accounts({
           user: user.publicKey,
           cfg: depositConfigPda,
@@ -342,9 +712,13 @@ describe("transaction-gateway", () => {
         depositWallet.publicKey
       );
 
-      // User balance should be exactly decreased by transfer amount (no fee in local validator)
+      // User pays the deposit plus rent for the new ledger (no fee in local validator)
+      const ledgerRent =
+        await provider.connection.getMinimumBalanceForRentExemption(
+          DEPOSIT_LEDGER_SPACE
+        );
       expect(userBalanceAfter).to.equal(
-        userBalanceBefore - DEPOSIT_SOL.toNumber()
+        userBalanceBefore - DEPOSIT_SOL.toNumber() - ledgerRent
       );
       // Deposit wallet balance should be exactly increased by transfer amount
       expect(depositWalletBalanceAfter).to.equal(
@@ -381,7 +755,7 @@ describe("transaction-gateway", () => {
       console.log("Deposit config PDA:", depositConfigPda.toString());
 
       const tx = await program.methods
-        .deposit(testMint, DEPOSIT_TOKEN)
+        .deposit(testMint, DEPOSIT_TOKEN, null)
         .accounts({
           user: user.publicKey,
           cfg: depositConfigPda,
@@ -457,10 +831,18 @@ describe("transaction-gateway", () => {
 
     try {
       await program.methods
-        .payMembership(SystemProgram.programId, WRONG_AMOUNT)
+        .payMembership(
+          0,
+          SystemProgram.programId,
+          WRONG_AMOUNT,
+          null,
+          null,
+          null
+        )
         .accounts({
           user: user.publicKey,
           cfg: membershipConfigPda,
+          membership: membershipPda,
           treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -483,10 +865,11 @@ describe("transaction-gateway", () => {
 
     try {
       await program.methods
-        .payMembership(unsupportedMint, MONTHLY_SOL)
+        .payMembership(0, unsupportedMint, MONTHLY_SOL, null, null, null)
         .accounts({
           user: user.publicKey,
           cfg: membershipConfigPda,
+          membership: membershipPda,
           treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -509,7 +892,7 @@ describe("transaction-gateway", () => {
 
     try {
       await program.methods
-        .deposit(unsupportedMint, DEPOSIT_SOL)
+        .deposit(unsupportedMint, DEPOSIT_SOL, null)
         .accounts({
           user: user.publicKey,
           cfg: depositConfigPda,
@@ -536,23 +919,17 @@ describe("transaction-gateway", () => {
     );
 
     // Add 2 new mints
-    const newMints = [
-      Keypair.generate().publicKey,
-      Keypair.generate().publicKey,
-    ];
+    const newMints = await createMints(2);
     const updatedAllowedMints = [...oldConfig.allowedMints, ...newMints];
 
     await program.methods
-      .updateDepositConfig(
-        admin.publicKey,
-        depositWallet.publicKey,
-        updatedAllowedMints
-      )
+      .updateDepositConfig(depositWallet.publicKey, updatedAllowedMints)
       .accounts({
         config: depositConfigPda,
-        admin: admin.publicKey,
+        authority: admin.publicKey,
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(mintMetas(newMints))
       .signers([admin])
       .rpc();
 
@@ -586,17 +963,18 @@ describe("transaction-gateway", () => {
       depositConfigPda
     );
 
-    // Add 10 new mints in smaller batches to avoid transaction size limit
-    const extra = [...Array(10)].map(() => Keypair.generate().publicKey);
+    // Add new mints in smaller batches to avoid transaction size limit
+    const extra = await createMints(5);
     const next = [...currentConfig.allowedMints, ...extra];
 
     await program.methods
-      .updateDepositConfig(admin.publicKey, depositWallet.publicKey, next)
+      .updateDepositConfig(depositWallet.publicKey, next)
       .accounts({
         config: depositConfigPda,
-        admin: admin.publicKey,
+        authority: admin.publicKey,
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(mintMetas(extra))
       .signers([admin])
       .rpc();
 
@@ -611,16 +989,17 @@ describe("transaction-gateway", () => {
     });
 
     // Test adding more mints to verify realloc works multiple times
-    const moreMints = [...Array(5)].map(() => Keypair.generate().publicKey);
+    const moreMints = await createMints(5);
     const final = [...next, ...moreMints];
 
     await program.methods
-      .updateDepositConfig(admin.publicKey, depositWallet.publicKey, final)
+      .updateDepositConfig(depositWallet.publicKey, final)
       .accounts({
         config: depositConfigPda,
-        admin: admin.publicKey,
+        authority: admin.publicKey,
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(mintMetas(moreMints))
       .signers([admin])
       .rpc();
 
@@ -636,4 +1015,2209 @@ describe("transaction-gateway", () => {
         .undefined;
     });
   });
+
+  it("Pauses and resumes membership payments", async () => {
+    await program.methods
+      .setMembershipPaused(true)
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    await expectError(
+      program.methods
+        .payMembership(
+          0,
+          SystemProgram.programId,
+          toLamports(0.1),
+          null,
+          null,
+          null
+        )
+        .accounts({
+          user: user.publicKey,
+          cfg: membershipConfigPda,
+          membership: membershipPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc(),
+      "GatewayPaused"
+    );
+
+    await program.methods
+      .setMembershipPaused(false)
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    const config = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(config.paused).to.be.false;
+  });
+
+  it("Lets a pauser pause a single deposit mint", async () => {
+    const pauser = Keypair.generate();
+    await airdrop(pauser.publicKey, 1);
+
+    await program.methods
+      .grantDepositRole(pauser.publicKey, { pauser: {} })
+      .accounts({
+        config: depositConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    await program.methods
+      .setDepositMintPaused(testMint, true)
+      .accounts({
+        config: depositConfigPda,
+        authority: pauser.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([pauser])
+      .rpc();
+
+    await expectError(
+      program.methods
+        .deposit(testMint, new anchor.BN(1_000), null)
+        .accounts({
+          user: user.publicKey,
+          cfg: depositConfigPda,
+          depositWallet: depositWallet.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          depositAta: depositAta,
+          mint: testMint,
+        })
+        .signers([user])
+        .rpc(),
+      "MintPaused"
+    );
+
+    await program.methods
+      .setDepositMintPaused(testMint, false)
+      .accounts({
+        config: depositConfigPda,
+        authority: pauser.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([pauser])
+      .rpc();
+
+    const config = await program.account.depositConfig.fetch(depositConfigPda);
+    expect(config.pausedMints.length).to.equal(0);
+  });
+
+  it("Rejects pausing by an unauthorized key", async () => {
+    await expectError(
+      program.methods
+        .setMembershipPaused(true)
+        .accounts({
+          config: membershipConfigPda,
+          authority: user.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc(),
+      "Unauthorized"
+    );
+  });
+
+  it("Transfers the deposit admin in two steps", async () => {
+    const newAdmin = Keypair.generate();
+    await airdrop(newAdmin.publicKey, 1);
+
+    await program.methods
+      .proposeDepositAdmin(newAdmin.publicKey)
+      .accounts({
+        config: depositConfigPda,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+
+    // Proposal alone does not change the admin
+    let config = await program.account.depositConfig.fetch(depositConfigPda);
+    expect(config.admin.toString()).to.equal(admin.publicKey.toString());
+    expect(config.pendingAdmin.toString()).to.equal(
+      newAdmin.publicKey.toString()
+    );
+
+    // Only the proposed key can accept
+    await expectError(
+      program.methods
+        .acceptDepositAdmin()
+        .accounts({
+          config: depositConfigPda,
+          newAdmin: user.publicKey,
+        })
+        .signers([user])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    await program.methods
+      .acceptDepositAdmin()
+      .accounts({
+        config: depositConfigPda,
+        newAdmin: newAdmin.publicKey,
+      })
+      .signers([newAdmin])
+      .rpc();
+
+    config = await program.account.depositConfig.fetch(depositConfigPda);
+    expect(config.admin.toString()).to.equal(newAdmin.publicKey.toString());
+    expect(config.pendingAdmin.toString()).to.equal(
+      PublicKey.default.toString()
+    );
+
+    // Hand it back so later tests keep using `admin`
+    await program.methods
+      .proposeDepositAdmin(admin.publicKey)
+      .accounts({
+        config: depositConfigPda,
+        admin: newAdmin.publicKey,
+      })
+      .signers([newAdmin])
+      .rpc();
+    await program.methods
+      .acceptDepositAdmin()
+      .accounts({
+        config: depositConfigPda,
+        newAdmin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Cancels a pending membership admin proposal", async () => {
+    const typo = Keypair.generate().publicKey;
+
+    await program.methods
+      .proposeMembershipAdmin(typo)
+      .accounts({
+        config: membershipConfigPda,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+
+    await program.methods
+      .cancelMembershipAdminProposal()
+      .accounts({
+        config: membershipConfigPda,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+
+    const config = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(config.admin.toString()).to.equal(admin.publicKey.toString());
+    expect(config.pendingAdmin.toString()).to.equal(
+      PublicKey.default.toString()
+    );
+  });
+
+  it("Queues treasury changes behind the timelock", async () => {
+    const newTreasury = Keypair.generate().publicKey;
+    const before = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+
+    await program.methods
+      .updateMembershipConfig(newTreasury, before.fees)
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    // Treasury is unchanged until the timelock elapses
+    const queued = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(queued.treasury.toString()).to.equal(treasury.publicKey.toString());
+    expect(queued.pendingTreasury.toString()).to.equal(newTreasury.toString());
+    expect(queued.treasuryEffectiveAt.toNumber()).to.be.greaterThan(
+      Math.floor(Date.now() / 1000) + DESTINATION_TIMELOCK - 60
+    );
+
+    await expectError(
+      program.methods
+        .applyPendingMembershipConfig()
+        .accounts({ config: membershipConfigPda })
+        .rpc(),
+      "TimelockNotElapsed"
+    );
+
+    await program.methods
+      .cancelPendingMembershipConfig()
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+
+    const cancelled = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(cancelled.treasury.toString()).to.equal(
+      treasury.publicKey.toString()
+    );
+    expect(cancelled.pendingTreasury.toString()).to.equal(
+      PublicKey.default.toString()
+    );
+  });
+
+  it("Lets a treasury manager queue destination changes alone", async () => {
+    const treasuryManager = Keypair.generate();
+    await airdrop(treasuryManager.publicKey, 1);
+
+    await program.methods
+      .grantMembershipRole(treasuryManager.publicKey, { treasuryManager: {} })
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+    await program.methods
+      .grantDepositRole(treasuryManager.publicKey, { treasuryManager: {} })
+      .accounts({
+        config: depositConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    // No fee manager role and no fee / mint list needed
+    const newTreasury = Keypair.generate().publicKey;
+    await program.methods
+      .queueTreasuryChange(newTreasury)
+      .accounts({
+        config: membershipConfigPda,
+        authority: treasuryManager.publicKey,
+      })
+      .signers([treasuryManager])
+      .rpc();
+    const newWallet = Keypair.generate().publicKey;
+    await program.methods
+      .queueDepositWalletChange(newWallet)
+      .accounts({
+        config: depositConfigPda,
+        authority: treasuryManager.publicKey,
+      })
+      .signers([treasuryManager])
+      .rpc();
+
+    const membershipConfig = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(membershipConfig.pendingTreasury.toString()).to.equal(
+      newTreasury.toString()
+    );
+    const depositConfig = await program.account.depositConfig.fetch(
+      depositConfigPda
+    );
+    expect(depositConfig.pendingDepositWallet.toString()).to.equal(
+      newWallet.toString()
+    );
+
+    await program.methods
+      .cancelPendingMembershipConfig()
+      .accounts({
+        config: membershipConfigPda,
+        authority: treasuryManager.publicKey,
+      })
+      .signers([treasuryManager])
+      .rpc();
+    await program.methods
+      .cancelPendingDepositConfig()
+      .accounts({
+        config: depositConfigPda,
+        authority: treasuryManager.publicKey,
+      })
+      .signers([treasuryManager])
+      .rpc();
+
+    await program.methods
+      .revokeMembershipRole(treasuryManager.publicKey, { treasuryManager: {} })
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+    await program.methods
+      .revokeDepositRole(treasuryManager.publicKey, { treasuryManager: {} })
+      .accounts({
+        config: depositConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Limits role holders to their granted role", async () => {
+    const feeManager = Keypair.generate();
+    await airdrop(feeManager.publicKey, 1);
+
+    await program.methods
+      .grantMembershipRole(feeManager.publicKey, { feeManager: {} })
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    // Fee manager can update fees...
+    const config = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    await program.methods
+      .updateMembershipConfig(config.treasury, config.fees)
+      .accounts({
+        config: membershipConfigPda,
+        authority: feeManager.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([feeManager])
+      .rpc();
+
+    // ...but cannot redirect the treasury or pause
+    await expectError(
+      program.methods
+        .updateMembershipConfig(Keypair.generate().publicKey, config.fees)
+        .accounts({
+          config: membershipConfigPda,
+          authority: feeManager.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([feeManager])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    await expectError(
+      program.methods
+        .setMembershipPaused(true)
+        .accounts({
+          config: membershipConfigPda,
+          authority: feeManager.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([feeManager])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    await program.methods
+      .revokeMembershipRole(feeManager.publicKey, { feeManager: {} })
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    const after = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(after.roles.length).to.equal(0);
+  });
+
+  it("Grows and shrinks MembershipConfig with the fee list", async () => {
+    const original = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    // Grow past the initial 1 KB one fee at a time to stay under the transaction size limit
+    const extraMints = await createMints(18);
+    for (const mint of extraMints) {
+      await program.methods
+        .upsertMembershipFee(
+          0,
+          mint,
+          new anchor.BN(1_000),
+          new anchor.BN(10_000),
+          null
+        )
+        .accounts({
+          config: membershipConfigPda,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(mintMetas([mint]))
+        .signers([admin])
+        .rpc();
+    }
+
+    const grown = await provider.connection.getAccountInfo(membershipConfigPda);
+    expect(grown.data.length).to.be.greaterThan(1024);
+    const grownConfig = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(grownConfig.fees.length).to.equal(
+      original.fees.length + extraMints.length
+    );
+
+    // Shrinking back refunds rent to the config admin
+    const adminBefore = await provider.connection.getBalance(admin.publicKey);
+    await program.methods
+      .updateMembershipConfig(original.treasury, original.fees)
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    const shrunk = await provider.connection.getAccountInfo(
+      membershipConfigPda
+    );
+    expect(shrunk.data.length).to.be.lessThan(grown.data.length);
+    expect(shrunk.lamports).to.equal(
+      await provider.connection.getMinimumBalanceForRentExemption(
+        shrunk.data.length
+      )
+    );
+    const adminAfter = await provider.connection.getBalance(admin.publicKey);
+    expect(adminAfter).to.be.greaterThan(adminBefore);
+  });
+
+  it("Upserts and removes a single membership fee", async () => {
+    const [newMint] = await createMints(1);
+    const before = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+
+    await program.methods
+      .upsertMembershipFee(
+        0,
+        newMint,
+        new anchor.BN(5_000),
+        new anchor.BN(50_000),
+        null
+      )
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(mintMetas([newMint]))
+      .signers([admin])
+      .rpc();
+
+    // Upserting again updates in place instead of duplicating
+    await program.methods
+      .upsertMembershipFee(
+        0,
+        newMint,
+        new anchor.BN(6_000),
+        new anchor.BN(60_000),
+        null
+      )
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    let config = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(config.fees.length).to.equal(before.fees.length + 1);
+    const fee = config.fees.find((f) => f.mint.equals(newMint));
+    expect(fee.monthlyFee.toNumber()).to.equal(6_000);
+    expect(fee.yearlyFee.toNumber()).to.equal(60_000);
+
+    await program.methods
+      .removeMembershipFee(0, newMint)
+      .accounts({
+        config: membershipConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    config = await program.account.membershipConfig.fetch(membershipConfigPda);
+    expect(config.fees.length).to.equal(before.fees.length);
+  });
+
+  it("Adds and removes a single allowed deposit mint", async () => {
+    const [newMint] = await createMints(1);
+
+    await program.methods
+      .addAllowedMint(newMint)
+      .accounts({
+        config: depositConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(mintMetas([newMint]))
+      .signers([admin])
+      .rpc();
+
+    await expectError(
+      program.methods
+        .addAllowedMint(newMint)
+        .accounts({
+          config: depositConfigPda,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc(),
+      "DuplicateMint"
+    );
+
+    await program.methods
+      .removeAllowedMint(newMint)
+      .accounts({
+        config: depositConfigPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    const config = await program.account.depositConfig.fetch(depositConfigPda);
+    expect(config.allowedMints.find((m) => m.equals(newMint))).to.be
+      .undefined;
+  });
+
+  it("Rejects invalid fee and mint configuration", async () => {
+    const config = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    const [mint] = await createMints(1);
+    const upsert = (monthly: number, yearly: number) =>
+      program.methods
+        .upsertMembershipFee(
+          0,
+          mint,
+          new anchor.BN(monthly),
+          new anchor.BN(yearly),
+          null
+        )
+        .accounts({
+          config: membershipConfigPda,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(mintMetas([mint]))
+        .signers([admin])
+        .rpc();
+
+    await expectError(upsert(0, 10_000), "ZeroFee");
+    await expectError(upsert(10_000, 10_000), "InvalidPlanPricing");
+
+    // Duplicate mints in a full fee list
+    await expectError(
+      program.methods
+        .updateMembershipConfig(config.treasury, [
+          ...config.fees,
+          config.fees[0],
+        ])
+        .accounts({
+          config: membershipConfigPda,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc(),
+      "DuplicateMint"
+    );
+
+    // Default pubkey as destination
+    await expectError(
+      program.methods
+        .updateDepositConfig(PublicKey.default, [SystemProgram.programId])
+        .accounts({
+          config: depositConfigPda,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc(),
+      "InvalidDestination"
+    );
+
+    // A random key or a missing mint account is not a mint
+    const notAMint = Keypair.generate().publicKey;
+    await expectError(
+      program.methods
+        .addAllowedMint(notAMint)
+        .accounts({
+          config: depositConfigPda,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(mintMetas([notAMint]))
+        .signers([admin])
+        .rpc(),
+      "InvalidMintAccount"
+    );
+    await expectError(
+      program.methods
+        .addAllowedMint(mint)
+        .accounts({
+          config: depositConfigPda,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc(),
+      "InvalidMintAccount"
+    );
+  });
+
+  it("Auto-renews only when due and stops after cancellation", async () => {
+    const [autoRenewalPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("auto_renewal"), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const allowance = new anchor.BN(300_000);
+
+    await program.methods
+      .enableAutoRenewal(testMint, allowance)
+      .accounts({
+        user: user.publicKey,
+        cfg: membershipConfigPda,
+        autoRenewal: autoRenewalPda,
+        userAta: userAta,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    let tokenAccount = await getAccount(provider.connection, userAta);
+    expect(tokenAccount.delegate.equals(autoRenewalPda)).to.be.true;
+    expect(Number(tokenAccount.delegatedAmount)).to.equal(
+      allowance.toNumber()
+    );
+
+    const crank = () =>
+      program.methods
+        .crankRenewal()
+        .accounts({
+          user: user.publicKey,
+          cfg: membershipConfigPda,
+          membership: membershipPda,
+          autoRenewal: autoRenewalPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: testMint,
+        })
+        .rpc();
+
+    // The membership is far from expiry, so the crank must not pull early
+    const membership = await program.account.membershipState.fetch(
+      membershipPda
+    );
+    expect(membership.expiresAt.toNumber()).to.be.greaterThan(
+      Date.now() / 1000 + RENEWAL_WINDOW
+    );
+    await expectError(crank(), "RenewalNotDue");
+
+    await program.methods
+      .cancelAutoRenewal()
+      .accounts({
+        user: user.publicKey,
+        autoRenewal: autoRenewalPda,
+        userAta: userAta,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    tokenAccount = await getAccount(provider.connection, userAta);
+    expect(tokenAccount.delegate).to.be.null;
+    expect(await provider.connection.getAccountInfo(autoRenewalPda)).to.be.null;
+
+    // Cancelled subscriptions can no longer be cranked
+    await expectError(crank(), "AccountNotInitialized");
+  });
+
+  it("Pulls the monthly fee when a renewal is due", async () => {
+    const MONTHLY_SOL = toLamports(0.1);
+    const MONTHLY_TOKEN = 100_000;
+    const allowance = new anchor.BN(300_000);
+    const member = Keypair.generate();
+    await airdrop(member.publicKey, 2);
+    const [memberPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const [autoRenewalPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("auto_renewal"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const [vaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("refund_vault")],
+      program.programId
+    );
+    const memberAta = await createAccount(
+      provider.connection,
+      admin,
+      testMint,
+      member.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin,
+      testMint,
+      memberAta,
+      admin,
+      1_000_000
+    );
+
+    // Pay, then refund so the membership ends now and the renewal is due
+    await program.methods
+      .payMembership(0, SystemProgram.programId, MONTHLY_SOL, null, null, null)
+      .accounts({
+        user: member.publicKey,
+        cfg: membershipConfigPda,
+        membership: memberPda,
+        treasury: treasury.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        mint: SystemProgram.programId,
+      })
+      .signers([member])
+      .rpc();
+    await airdrop(vaultPda, 1);
+    await program.methods
+      .refundMembership()
+      .accounts({
+        authority: admin.publicKey,
+        cfg: membershipConfigPda,
+        membership: memberPda,
+        vault: vaultPda,
+        recipient: member.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        mint: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+    const before = await program.account.membershipState.fetch(memberPda);
+
+    await program.methods
+      .enableAutoRenewal(testMint, allowance)
+      .accounts({
+        user: member.publicKey,
+        cfg: membershipConfigPda,
+        autoRenewal: autoRenewalPda,
+        userAta: memberAta,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([member])
+      .rpc();
+    const crank = (destinationAta: PublicKey) =>
+      program.methods
+        .crankRenewal()
+        .accounts({
+          user: member.publicKey,
+          cfg: membershipConfigPda,
+          membership: memberPda,
+          autoRenewal: autoRenewalPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: memberAta,
+          treasuryAta: destinationAta,
+          mint: testMint,
+        })
+        .rpc();
+
+    // The fee can't be redirected to an account the treasury doesn't own
+    await expectError(crank(userAta), "InvalidTokenAccount");
+
+    const treasuryBefore = Number(
+      (await getAccount(provider.connection, treasuryAta)).amount
+    );
+    await crank(treasuryAta);
+    expect(
+      Number((await getAccount(provider.connection, treasuryAta)).amount)
+    ).to.equal(treasuryBefore + MONTHLY_TOKEN);
+
+    const after = await program.account.membershipState.fetch(memberPda);
+    expect(after.expiresAt.toNumber()).to.be.at.least(
+      before.expiresAt.toNumber() + MONTHLY_PERIOD
+    );
+    expect(after.expiresAt.toNumber()).to.be.at.most(
+      before.expiresAt.toNumber() + MONTHLY_PERIOD + 60
+    );
+    const memberAccount = await getAccount(provider.connection, memberAta);
+    expect(Number(memberAccount.delegatedAmount)).to.equal(
+      allowance.toNumber() - MONTHLY_TOKEN
+    );
+  });
+
+  it("Prorates plan upgrades and downgrades", async () => {
+    const member = Keypair.generate();
+    await airdrop(member.publicKey, 5);
+    const [memberPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const MONTHLY_SOL = toLamports(0.1);
+    const YEARLY_SOL = toLamports(1.0);
+
+    await program.methods
+      .payMembership(0, SystemProgram.programId, MONTHLY_SOL, null, null, null)
+      .accounts({
+        user: member.publicKey,
+        cfg: membershipConfigPda,
+        membership: memberPda,
+        treasury: treasury.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        userAta: userAta,
+        treasuryAta: treasuryAta,
+        mint: SystemProgram.programId,
+      })
+      .signers([member])
+      .rpc();
+
+    const changePlan = (plan: object, maxAmount: anchor.BN) =>
+      program.methods
+        .changePlan(plan as any, maxAmount)
+        .accounts({
+          user: member.publicKey,
+          cfg: membershipConfigPda,
+          membership: memberPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: SystemProgram.programId,
+        })
+        .signers([member])
+        .rpc();
+
+    // Same plan and charges above the cap are rejected
+    for (const [plan, max, code] of [
+      [{ monthly: {} }, YEARLY_SOL, "SamePlan"],
+      [{ yearly: {} }, toLamports(0.5), "PlanChangeExceedsMax"],
+    ] as [object, anchor.BN, string][]) {
+      await expectError(changePlan(plan, max), code);
+    }
+
+    // Upgrade: pay the yearly price minus the unused monthly time
+    const treasuryBefore = await provider.connection.getBalance(
+      treasury.publicKey
+    );
+    await changePlan({ yearly: {} }, YEARLY_SOL);
+    const charged =
+      (await provider.connection.getBalance(treasury.publicKey)) -
+      treasuryBefore;
+    expect(charged).to.be.greaterThan(
+      YEARLY_SOL.sub(MONTHLY_SOL).toNumber()
+    );
+    expect(charged).to.be.lessThan(
+      YEARLY_SOL.sub(MONTHLY_SOL).add(toLamports(0.001)).toNumber()
+    );
+
+    let state = await program.account.membershipState.fetch(memberPda);
+    expect(state.plan).to.deep.equal({ yearly: {} });
+    const upgradedAt = state.expiresAt.toNumber() - YEARLY_PERIOD;
+
+    // Downgrade: the yearly credit covers the monthly price and becomes extra time
+    await changePlan({ monthly: {} }, new anchor.BN(0));
+    state = await program.account.membershipState.fetch(memberPda);
+    expect(state.plan).to.deep.equal({ monthly: {} });
+    expect(state.expiresAt.toNumber()).to.be.greaterThan(
+      upgradedAt + 9 * MONTHLY_PERIOD
+    );
+  });
+
+  it("Credits plan changes with the value actually paid", async () => {
+    const member = Keypair.generate();
+    await airdrop(member.publicKey, 5);
+    const [memberPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const MONTHLY_SOL = toLamports(0.1);
+    const YEARLY_SOL = toLamports(1.0);
+    const accounts = {
+      user: member.publicKey,
+      cfg: membershipConfigPda,
+      membership: memberPda,
+      treasury: treasury.publicKey,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      mint: SystemProgram.programId,
+    };
+
+    // A monthly renewal stacked on a yearly membership
+    for (const amount of [YEARLY_SOL, MONTHLY_SOL]) {
+      await program.methods
+        .payMembership(0, SystemProgram.programId, amount, null, null, null)
+        .accounts(accounts)
+        .signers([member])
+        .rpc();
+    }
+    let state = await program.account.membershipState.fetch(memberPda);
+    expect(state.plan).to.deep.equal({ monthly: {} });
+    expect(state.paidValue.toString()).to.equal(
+      YEARLY_SOL.add(MONTHLY_SOL).toString()
+    );
+    const paidUntil = state.expiresAt.toNumber();
+
+    // Switching to yearly credits the 1.1 SOL paid, not 13 months at the monthly price
+    const treasuryBefore = await provider.connection.getBalance(
+      treasury.publicKey
+    );
+    await program.methods
+      .changePlan({ yearly: {} }, new anchor.BN(0))
+      .accounts(accounts)
+      .signers([member])
+      .rpc();
+    expect(
+      await provider.connection.getBalance(treasury.publicKey)
+    ).to.equal(treasuryBefore);
+
+    state = await program.account.membershipState.fetch(memberPda);
+    expect(state.plan).to.deep.equal({ yearly: {} });
+    expect(state.paidValue.toNumber()).to.be.at.most(
+      YEARLY_SOL.add(MONTHLY_SOL).toNumber()
+    );
+    expect(state.expiresAt.toNumber()).to.be.lessThan(
+      paidUntil - (YEARLY_PERIOD + MONTHLY_PERIOD) + 1.1 * YEARLY_PERIOD + 60
+    );
+  });
+
+  it("Sells multiple named tiers", async () => {
+    const PRO = 7;
+    const PRO_MONTHLY = toLamports(0.3);
+    const adminAccounts = {
+      config: membershipConfigPda,
+      authority: admin.publicKey,
+      systemProgram: SystemProgram.programId,
+    };
+
+    await program.methods
+      .upsertMembershipTier(PRO, "Pro")
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+    await program.methods
+      .upsertMembershipFee(
+        PRO,
+        SystemProgram.programId,
+        PRO_MONTHLY,
+        toLamports(3.0),
+        null
+      )
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+
+    const payTier = (payer: Keypair, tier: number, amount: anchor.BN) =>
+      program.methods
+        .payMembership(tier, SystemProgram.programId, amount, null, null, null)
+        .accounts({
+          user: payer.publicKey,
+          cfg: membershipConfigPda,
+          membership: PublicKey.findProgramAddressSync(
+            [Buffer.from("membership"), payer.publicKey.toBuffer()],
+            program.programId
+          )[0],
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: SystemProgram.programId,
+        })
+        .signers([payer])
+        .rpc();
+
+    // The event and the membership record the tier
+    const member = Keypair.generate();
+    await airdrop(member.publicKey, 2);
+    let paidTier: number;
+    const listener = program.addEventListener("membershipPaid", (event) => {
+      if (event.user.equals(member.publicKey)) paidTier = event.tier;
+    });
+    await payTier(member, PRO, PRO_MONTHLY);
+    await new Promise((resolve) => setTimeout(resolve, 1_000));
+    await program.removeEventListener(listener);
+    expect(paidTier).to.equal(PRO);
+    const [memberPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const state = await program.account.membershipState.fetch(memberPda);
+    expect(state.tier).to.equal(PRO);
+
+    // An active Basic member cannot top up with Pro time
+    await expectError(payTier(user, PRO, PRO_MONTHLY), "TierMismatch");
+
+    // Removing the tier also removes its prices
+    await program.methods
+      .removeMembershipTier(PRO)
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+    const config = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(config.tiers.find((t) => t.id === PRO)).to.be.undefined;
+    expect(config.fees.find((f) => f.tier === PRO)).to.be.undefined;
+    await expectError(payTier(member, PRO, PRO_MONTHLY), "UnknownTier");
+  });
+
+  it("Redeems a coupon once per wallet", async () => {
+    const code = "LAUNCH50";
+    const codeHash = createHash("sha256").update(code).digest();
+    const [couponPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("coupon"), codeHash],
+      program.programId
+    );
+    const MONTHLY_SOL = toLamports(0.1);
+
+    await program.methods
+      .createCoupon(
+        [...codeHash],
+        { percent: { bps: 5_000 } },
+        SystemProgram.programId,
+        10,
+        new anchor.BN(Math.floor(Date.now() / 1000) + 3_600)
+      )
+      .accounts({
+        config: membershipConfigPda,
+        coupon: couponPda,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    const member = Keypair.generate();
+    await airdrop(member.publicKey, 2);
+    const [memberPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const [redemptionPda] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("coupon_redemption"),
+        couponPda.toBuffer(),
+        member.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+    const payWithCoupon = (amount: anchor.BN, couponCode: string) =>
+      program.methods
+        .payMembership(
+          0,
+          SystemProgram.programId,
+          amount,
+          null,
+          couponCode,
+          null
+        )
+        .accounts({
+          user: member.publicKey,
+          cfg: membershipConfigPda,
+          membership: memberPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: SystemProgram.programId,
+          coupon: couponPda,
+          couponRedemption: redemptionPda,
+        })
+        .signers([member])
+        .rpc();
+
+    // Wrong code and full price are rejected
+    for (const [amount, couponCode, error] of [
+      [MONTHLY_SOL.divn(2), "LAUNCH5O", "InvalidCoupon"],
+      [MONTHLY_SOL, code, "WrongFee"],
+    ] as [anchor.BN, string, string][]) {
+      await expectError(payWithCoupon(amount, couponCode), error);
+    }
+
+    const treasuryBefore = await provider.connection.getBalance(
+      treasury.publicKey
+    );
+    await payWithCoupon(MONTHLY_SOL.divn(2), code);
+    expect(
+      (await provider.connection.getBalance(treasury.publicKey)) -
+        treasuryBefore
+    ).to.equal(MONTHLY_SOL.divn(2).toNumber());
+
+    const coupon = await program.account.coupon.fetch(couponPda);
+    expect(coupon.redemptions).to.equal(1);
+    const redemption = await program.account.couponRedemption.fetch(
+      redemptionPda
+    );
+    expect(redemption.user.equals(member.publicKey)).to.be.true;
+
+    // The same wallet cannot redeem twice
+    await expectError(
+      payWithCoupon(MONTHLY_SOL.divn(2), code),
+      "CouponAlreadyRedeemed"
+    );
+
+    await program.methods
+      .closeCoupon()
+      .accounts({
+        config: membershipConfigPda,
+        coupon: couponPda,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+    expect(await provider.connection.getAccountInfo(couponPda)).to.be.null;
+  });
+
+  it("Splits a referral share off membership payments", async () => {
+    const MONTHLY_SOL = toLamports(0.1);
+    const adminAccounts = {
+      config: membershipConfigPda,
+      authority: admin.publicKey,
+      systemProgram: SystemProgram.programId,
+    };
+    await program.methods
+      .setReferralBps(1_000)
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+
+    const member = Keypair.generate();
+    const referrer = Keypair.generate();
+    await airdrop(member.publicKey, 2);
+    await airdrop(referrer.publicKey, 1);
+    const [memberPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const statsPda = (key: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("referral_stats"),
+          key.toBuffer(),
+          SystemProgram.programId.toBuffer(),
+        ],
+        program.programId
+      )[0];
+    const payReferred = (referrerKey: PublicKey) =>
+      program.methods
+        .payMembership(
+          0,
+          SystemProgram.programId,
+          MONTHLY_SOL,
+          null,
+          null,
+          null
+        )
+        .accounts({
+          user: member.publicKey,
+          cfg: membershipConfigPda,
+          membership: memberPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: SystemProgram.programId,
+          referrer: referrerKey,
+          referralStats: statsPda(referrerKey),
+        })
+        .signers([member])
+        .rpc();
+
+    await expectError(payReferred(member.publicKey), "SelfReferral");
+
+    const treasuryBefore = await provider.connection.getBalance(
+      treasury.publicKey
+    );
+    const referrerBefore = await provider.connection.getBalance(
+      referrer.publicKey
+    );
+    await payReferred(referrer.publicKey);
+
+    const share = MONTHLY_SOL.divn(10).toNumber();
+    expect(
+      (await provider.connection.getBalance(referrer.publicKey)) -
+        referrerBefore
+    ).to.equal(share);
+    expect(
+      (await provider.connection.getBalance(treasury.publicKey)) -
+        treasuryBefore
+    ).to.equal(MONTHLY_SOL.toNumber() - share);
+
+    const stats = await program.account.referralStats.fetch(
+      statsPda(referrer.publicKey)
+    );
+    expect(stats.referrals.toNumber()).to.equal(1);
+    expect(stats.totalEarned.toNumber()).to.equal(share);
+
+    // Only the treasury's part can be refunded
+    const state = await program.account.membershipState.fetch(memberPda);
+    expect(state.lastAmount.toNumber()).to.equal(
+      MONTHLY_SOL.toNumber() - share
+    );
+
+    await program.methods
+      .setReferralBps(0)
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Validates and timelocks revenue splits", async () => {
+    const operations = Keypair.generate().publicKey;
+    const partner = Keypair.generate().publicKey;
+    const accounts = {
+      config: membershipConfigPda,
+      authority: admin.publicKey,
+      systemProgram: SystemProgram.programId,
+    };
+
+    await expectError(
+      program.methods
+        .queueRevenueSplits([
+          { recipient: operations, bps: 6_000 },
+          { recipient: partner, bps: 3_000 },
+        ])
+        .accounts(accounts)
+        .signers([admin])
+        .rpc(),
+      "InvalidRevenueSplits"
+    );
+
+    await program.methods
+      .queueRevenueSplits([
+        { recipient: operations, bps: 7_000 },
+        { recipient: partner, bps: 3_000 },
+      ])
+      .accounts(accounts)
+      .signers([admin])
+      .rpc();
+
+    let config = await program.account.membershipConfig.fetch(
+      membershipConfigPda
+    );
+    expect(config.pendingRevenueSplits.length).to.equal(2);
+    expect(config.revenueSplitsEffectiveAt.toNumber()).to.be.greaterThan(
+      Date.now() / 1000 + DESTINATION_TIMELOCK - 60
+    );
+
+    await expectError(
+      program.methods
+        .applyPendingRevenueSplits()
+        .accounts({ config: membershipConfigPda })
+        .rpc(),
+      "TimelockNotElapsed"
+    );
+
+    await program.methods
+      .cancelPendingRevenueSplits()
+      .accounts({ config: membershipConfigPda, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    config = await program.account.membershipConfig.fetch(membershipConfigPda);
+    expect(config.pendingRevenueSplits).to.be.null;
+    expect(config.revenueSplits.length).to.equal(0);
+  });
+
+  it("Gifts a membership to another wallet", async () => {
+    const MONTHLY_SOL = toLamports(0.1);
+    const beneficiary = Keypair.generate().publicKey;
+    const [beneficiaryPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), beneficiary.toBuffer()],
+      program.programId
+    );
+    const payerBefore = await program.account.membershipState.fetch(
+      membershipPda
+    );
+
+    let gift: { user: PublicKey; payer: PublicKey };
+    const listener = program.addEventListener("membershipPaid", (event) => {
+      if (event.user.equals(beneficiary)) gift = event;
+    });
+    await program.methods
+      .giftMembership(
+        beneficiary,
+        0,
+        SystemProgram.programId,
+        MONTHLY_SOL,
+        null,
+        null,
+        null
+      )
+      .accounts({
+        user: user.publicKey,
+        cfg: membershipConfigPda,
+        membership: beneficiaryPda,
+        treasury: treasury.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        userAta: userAta,
+        treasuryAta: treasuryAta,
+        mint: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 1_000));
+    await program.removeEventListener(listener);
+
+    expect(gift.payer.equals(user.publicKey)).to.be.true;
+    const state = await program.account.membershipState.fetch(beneficiaryPda);
+    expect(state.user.equals(beneficiary)).to.be.true;
+    expect(state.expiresAt.toNumber()).to.equal(
+      state.startedAt.toNumber() + MONTHLY_PERIOD
+    );
+
+    // The payer's own membership is untouched
+    const payerAfter = await program.account.membershipState.fetch(
+      membershipPda
+    );
+    expect(payerAfter.expiresAt.toNumber()).to.equal(
+      payerBefore.expiresAt.toNumber()
+    );
+
+    // The membership account must belong to the beneficiary
+    await expectError(
+      program.methods
+        .giftMembership(
+          beneficiary,
+          0,
+          SystemProgram.programId,
+          MONTHLY_SOL,
+          null,
+          null,
+          null
+        )
+        .accounts({
+          user: user.publicKey,
+          cfg: membershipConfigPda,
+          membership: membershipPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc(),
+      "InvalidMembershipAccount"
+    );
+  });
+
+  it("Keeps gifts off other members' tier and refund records", async () => {
+    const TRIAL_TIER = 9;
+    const MONTHLY_SOL = toLamports(0.1);
+    const adminAccounts = {
+      config: membershipConfigPda,
+      authority: admin.publicKey,
+      systemProgram: SystemProgram.programId,
+    };
+    const member = Keypair.generate();
+    const gifter = Keypair.generate();
+    await airdrop(member.publicKey, 2);
+    await airdrop(gifter.publicKey, 2);
+    const [memberPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const pay = (payer: Keypair) =>
+      program.methods
+        .giftMembership(
+          member.publicKey,
+          0,
+          SystemProgram.programId,
+          MONTHLY_SOL,
+          null,
+          null,
+          null
+        )
+        .accounts({
+          user: payer.publicKey,
+          cfg: membershipConfigPda,
+          membership: memberPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          mint: SystemProgram.programId,
+        })
+        .signers([payer])
+        .rpc();
+
+    await program.methods
+      .upsertMembershipTier(TRIAL_TIER, "Trial")
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+    await program.methods
+      .setTrialConfig(true, 7, TRIAL_TIER)
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+    await program.methods
+      .startTrial()
+      .accounts({
+        user: member.publicKey,
+        cfg: membershipConfigPda,
+        membership: memberPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([member])
+      .rpc();
+
+    // Only the member can convert the trial to another tier
+    await expectError(pay(gifter), "TierMismatch");
+    await program.methods
+      .setRefundWindow(new anchor.BN(3_600))
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+    await pay(member);
+    let state = await program.account.membershipState.fetch(memberPda);
+    expect(state.tier).to.equal(0);
+
+    // The member's payment stays refundable to the member
+    await expectError(pay(gifter), "RefundPending");
+    state = await program.account.membershipState.fetch(memberPda);
+    expect(state.lastPayer.equals(member.publicKey)).to.be.true;
+
+    await program.methods
+      .setRefundWindow(new anchor.BN(0))
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+    await pay(gifter);
+    state = await program.account.membershipState.fetch(memberPda);
+    expect(state.lastPayer.equals(gifter.publicKey)).to.be.true;
+
+    await program.methods
+      .setTrialConfig(false, 0, 0)
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+    await program.methods
+      .removeMembershipTier(TRIAL_TIER)
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Grants one free trial per wallet", async () => {
+    const adminAccounts = {
+      config: membershipConfigPda,
+      authority: admin.publicKey,
+      systemProgram: SystemProgram.programId,
+    };
+    const trialist = Keypair.generate();
+    await airdrop(trialist.publicKey, 1);
+    const [trialistPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), trialist.publicKey.toBuffer()],
+      program.programId
+    );
+    const startTrial = () =>
+      program.methods
+        .startTrial()
+        .accounts({
+          user: trialist.publicKey,
+          cfg: membershipConfigPda,
+          membership: trialistPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([trialist])
+        .rpc();
+
+    // Trials are off by default
+    await expectError(startTrial(), "TrialsDisabled");
+
+    await expectError(
+      program.methods
+        .setTrialConfig(true, 0, 0)
+        .accounts(adminAccounts)
+        .signers([admin])
+        .rpc(),
+      "InvalidTrialDays"
+    );
+
+    await program.methods
+      .setTrialConfig(true, 7, 0)
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+
+    await startTrial();
+    const membership = await program.account.membershipState.fetch(
+      trialistPda
+    );
+    expect(membership.user.equals(trialist.publicKey)).to.be.true;
+    expect(membership.tier).to.equal(0);
+    expect(membership.expiresAt.toNumber()).to.equal(
+      membership.startedAt.toNumber() + 7 * 86_400
+    );
+    expect(membership.trialEndsAt.toNumber()).to.equal(
+      membership.expiresAt.toNumber()
+    );
+
+    // A wallet can trial only once
+    await expectError(startTrial(), "TrialAlreadyUsed");
+
+    await program.methods
+      .setTrialConfig(false, 0, 0)
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Refunds membership payments from the refund vault", async () => {
+    const MONTHLY_SOL = toLamports(0.1);
+    const adminAccounts = {
+      config: membershipConfigPda,
+      authority: admin.publicKey,
+      systemProgram: SystemProgram.programId,
+    };
+    const member = Keypair.generate();
+    await airdrop(member.publicKey, 2);
+    const [memberPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), member.publicKey.toBuffer()],
+      program.programId
+    );
+    const [vaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("refund_vault")],
+      program.programId
+    );
+    await airdrop(vaultPda, 1);
+
+    const pay = () =>
+      program.methods
+        .payMembership(
+          0,
+          SystemProgram.programId,
+          MONTHLY_SOL,
+          null,
+          null,
+          null
+        )
+        .accounts({
+          user: member.publicKey,
+          cfg: membershipConfigPda,
+          membership: memberPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: SystemProgram.programId,
+        })
+        .signers([member])
+        .rpc();
+    const refund = (authority: Keypair) =>
+      program.methods
+        .refundMembership()
+        .accounts({
+          authority: authority.publicKey,
+          cfg: membershipConfigPda,
+          membership: memberPda,
+          vault: vaultPda,
+          recipient: member.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          vaultAta: userAta,
+          recipientAta: userAta,
+          mint: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+    // Without a refund window only a treasury manager can refund
+    await pay();
+    await expectError(refund(member), "Unauthorized");
+
+    const paid = await program.account.membershipState.fetch(memberPda);
+    expect(paid.payments.toNumber()).to.equal(1);
+    expect(paid.lastAmount.toNumber()).to.equal(MONTHLY_SOL.toNumber());
+
+    const balanceBefore = await provider.connection.getBalance(
+      member.publicKey
+    );
+    await refund(admin);
+    expect(await provider.connection.getBalance(member.publicKey)).to.equal(
+      balanceBefore + MONTHLY_SOL.toNumber()
+    );
+    const refunded = await program.account.membershipState.fetch(memberPda);
+    expect(refunded.lastAmount.toNumber()).to.equal(0);
+    expect(refunded.expiresAt.toNumber()).to.be.at.most(
+      Math.floor(Date.now() / 1000) + 60
+    );
+
+    // A payment is refunded at most once
+    await expectError(refund(admin), "NothingToRefund");
+
+    // Within the window the payer refunds without approval
+    await program.methods
+      .setRefundWindow(new anchor.BN(3_600))
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+    await pay();
+    await refund(member);
+    const selfRefunded = await program.account.membershipState.fetch(
+      memberPda
+    );
+    expect(selfRefunded.payments.toNumber()).to.equal(2);
+    expect(selfRefunded.lastAmount.toNumber()).to.equal(0);
+
+    await program.methods
+      .setRefundWindow(new anchor.BN(0))
+      .accounts(adminAccounts)
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Holds deposits in the program vault in vault mode", async () => {
+    const DEPOSIT_SOL = toLamports(1);
+    const DEPOSIT_TOKEN = new anchor.BN(1_000);
+    const walletAccounts = {
+      config: depositConfigPda,
+      authority: admin.publicKey,
+    };
+    const [vaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("deposit_vault")],
+      program.programId
+    );
+    const [vaultAta] = PublicKey.findProgramAddressSync(
+      [Buffer.from("deposit_vault"), testMint.toBuffer()],
+      program.programId
+    );
+    const deposit = (
+      mint: PublicKey,
+      amount: anchor.BN,
+      destination: PublicKey,
+      destinationAta: PublicKey | null
+    ) =>
+      program.methods
+        .deposit(mint, amount, null)
+        .accounts({
+          user: user.publicKey,
+          cfg: depositConfigPda,
+          depositWallet: destination,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          depositAta: destinationAta,
+          mint,
+        })
+        .signers([user])
+        .rpc();
+
+    await program.methods
+      .setDepositVaultMode(true)
+      .accounts(walletAccounts)
+      .signers([admin])
+      .rpc();
+
+    // The deposit wallet no longer receives deposits
+    await expectError(
+      deposit(
+        SystemProgram.programId,
+        DEPOSIT_SOL,
+        depositWallet.publicKey,
+        depositAta
+      ),
+      "ConstraintAddress"
+    );
+
+    await deposit(SystemProgram.programId, DEPOSIT_SOL, vaultPda, null);
+    expect(await provider.connection.getBalance(vaultPda)).to.equal(
+      DEPOSIT_SOL.toNumber()
+    );
+
+    // SPL deposits need the vault token account of the mint
+    await program.methods
+      .createDepositVault()
+      .accounts({
+        config: depositConfigPda,
+        vault: vaultPda,
+        vaultAta,
+        mint: testMint,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([admin])
+      .rpc();
+    await expectError(
+      deposit(testMint, DEPOSIT_TOKEN, vaultPda, depositAta),
+      "ConstraintTokenOwner"
+    );
+    await deposit(testMint, DEPOSIT_TOKEN, vaultPda, vaultAta);
+    const vaultAccount = await getAccount(provider.connection, vaultAta);
+    expect(vaultAccount.owner.equals(vaultPda)).to.be.true;
+    expect(Number(vaultAccount.amount)).to.equal(DEPOSIT_TOKEN.toNumber());
+
+    // Sweeps only go to whitelisted destinations
+    await expectError(
+      program.methods
+        .sweepVault(SystemProgram.programId, DEPOSIT_SOL)
+        .accounts({
+          config: depositConfigPda,
+          authority: admin.publicKey,
+          vault: vaultPda,
+          destination: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          vaultAta,
+          destinationAta: treasuryAta,
+          mint: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc(),
+      "InvalidDestination"
+    );
+
+    // Whitelist changes are timelocked
+    await program.methods
+      .queueSweepDestinations([treasury.publicKey])
+      .accounts({
+        ...walletAccounts,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+    let config = await program.account.depositConfig.fetch(depositConfigPda);
+    expect(config.sweepDestinations).to.be.empty;
+    expect(config.pendingSweepDestinations[0].equals(treasury.publicKey)).to.be
+      .true;
+    await expectError(
+      program.methods
+        .applyPendingSweepDestinations()
+        .accounts({ config: depositConfigPda })
+        .rpc(),
+      "TimelockNotElapsed"
+    );
+    await program.methods
+      .cancelPendingSweepDestinations()
+      .accounts(walletAccounts)
+      .signers([admin])
+      .rpc();
+    config = await program.account.depositConfig.fetch(depositConfigPda);
+    expect(config.pendingSweepDestinations).to.be.null;
+
+    await program.methods
+      .setDepositVaultMode(false)
+      .accounts(walletAccounts)
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Tracks deposits per user and mint in the ledger", async () => {
+    const DEPOSIT_TOKEN = new anchor.BN(1_000);
+    const [ledgerPda] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("deposit_ledger"),
+        user.publicKey.toBuffer(),
+        testMint.toBuffer(),
+      ],
+      program.programId
+    );
+    const before = await program.account.userDepositLedger.fetch(ledgerPda);
+
+    await program.methods
+      .deposit(testMint, DEPOSIT_TOKEN, null)
+      .accounts({
+        user: user.publicKey,
+        cfg: depositConfigPda,
+        ledger: ledgerPda,
+        depositWallet: depositWallet.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        userAta: userAta,
+        depositAta: depositAta,
+        mint: testMint,
+      })
+      .signers([user])
+      .rpc();
+
+    const ledger = await program.account.userDepositLedger.fetch(ledgerPda);
+    expect(ledger.user.equals(user.publicKey)).to.be.true;
+    expect(ledger.mint.equals(testMint)).to.be.true;
+    expect(ledger.totalDeposited.toNumber()).to.equal(
+      before.totalDeposited.toNumber() + DEPOSIT_TOKEN.toNumber()
+    );
+    expect(ledger.depositCount.toNumber()).to.equal(
+      before.depositCount.toNumber() + 1
+    );
+    expect(ledger.lastDepositAt.toNumber()).to.be.at.least(
+      before.lastDepositAt.toNumber()
+    );
+  });
+
+  it("Enforces per-mint deposit limits", async () => {
+    const adminAccounts = {
+      config: depositConfigPda,
+      authority: admin.publicKey,
+      systemProgram: SystemProgram.programId,
+    };
+    const depositor = Keypair.generate();
+    await airdrop(depositor.publicKey, 1);
+    const depositorAta = await createAccount(
+      provider.connection,
+      admin,
+      testMint,
+      depositor.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin,
+      testMint,
+      depositorAta,
+      admin,
+      100_000
+    );
+    const setLimits = (min: number, max: number, daily: number, tvl: number) =>
+      program.methods
+        .setDepositLimits(
+          testMint,
+          new anchor.BN(min),
+          new anchor.BN(max),
+          new anchor.BN(daily),
+          new anchor.BN(tvl)
+        )
+        .accounts(adminAccounts)
+        .signers([admin])
+        .rpc();
+    const deposit = (amount: number, destinationAta = depositAta) =>
+      program.methods
+        .deposit(testMint, new anchor.BN(amount), null)
+        .accounts({
+          user: depositor.publicKey,
+          cfg: depositConfigPda,
+          depositWallet: depositWallet.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: depositorAta,
+          depositAta: destinationAta,
+          mint: testMint,
+        })
+        .signers([depositor])
+        .rpc();
+    await expectError(setLimits(200, 100, 0, 0), "InvalidDepositLimits");
+    await setLimits(100, 10_000, 15_000, 0);
+    let config = await program.account.depositConfig.fetch(depositConfigPda);
+    expect(config.depositLimits).to.have.lengthOf(1);
+
+    await expectError(deposit(0), "DepositBelowMinimum");
+    await expectError(deposit(50), "DepositBelowMinimum");
+    await expectError(deposit(20_000), "DepositAboveMaximum");
+    await deposit(10_000);
+    await expectError(deposit(6_000), "DailyDepositCapExceeded");
+    await deposit(5_000);
+
+    // An empty token account not owned by the deposit wallet can't stand in for it
+    const foreignAta = await createAccount(
+      provider.connection,
+      admin,
+      testMint,
+      depositor.publicKey,
+      Keypair.generate()
+    );
+    await expectError(deposit(1_000, foreignAta), "ConstraintTokenOwner");
+
+    // The TVL cap bounds the vault balance, so it needs vault mode
+    await expectError(setLimits(0, 0, 0, 1_000), "TvlCapRequiresVault");
+    const walletAccounts = {
+      config: depositConfigPda,
+      authority: admin.publicKey,
+    };
+    const setVaultMode = (enabled: boolean) =>
+      program.methods
+        .setDepositVaultMode(enabled)
+        .accounts(walletAccounts)
+        .signers([admin])
+        .rpc();
+    const [vaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("deposit_vault")],
+      program.programId
+    );
+    const [vaultAta] = PublicKey.findProgramAddressSync(
+      [Buffer.from("deposit_vault"), testMint.toBuffer()],
+      program.programId
+    );
+    const vaultDeposit = (amount: number, destinationAta = vaultAta) =>
+      program.methods
+        .deposit(testMint, new anchor.BN(amount), null)
+        .accounts({
+          user: depositor.publicKey,
+          cfg: depositConfigPda,
+          depositWallet: vaultPda,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: depositorAta,
+          depositAta: destinationAta,
+          mint: testMint,
+        })
+        .signers([depositor])
+        .rpc();
+    await setVaultMode(true);
+    const balance = Number(
+      (await getAccount(provider.connection, vaultAta)).amount
+    );
+    await setLimits(0, 0, 0, balance + 1_000);
+    await expectError(vaultDeposit(2_000), "TvlCapExceeded");
+
+    // A fresh, empty token account of the vault can't reset the balance
+    const emptyVaultAta = await createAccount(
+      provider.connection,
+      admin,
+      testMint,
+      vaultPda,
+      Keypair.generate()
+    );
+    await expectError(
+      vaultDeposit(2_000, emptyVaultAta),
+      "InvalidVaultAccount"
+    );
+    await vaultDeposit(1_000);
+    await expectError(vaultDeposit(1), "TvlCapExceeded");
+    await expectError(setVaultMode(false), "TvlCapRequiresVault");
+
+    // All zeros removes the limits
+    await setLimits(0, 0, 0, 0);
+    await setVaultMode(false);
+    config = await program.account.depositConfig.fetch(depositConfigPda);
+    expect(config.depositLimits).to.be.empty;
+  });
+
+  it("Pays each reference at most once with a reference marker", async () => {
+    const DEPOSIT_SOL = toLamports(0.01);
+    const MONTHLY_SOL = toLamports(0.1);
+    const reference = [...createHash("sha256").update("order-1").digest()];
+    const markerPda = (payer: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("reference"), payer.toBuffer(), Buffer.from(reference)],
+        program.programId
+      )[0];
+    const deposit = (payer = user, marker = markerPda(payer.publicKey)) =>
+      program.methods
+        .deposit(SystemProgram.programId, DEPOSIT_SOL, reference)
+        .accounts({
+          user: payer.publicKey,
+          cfg: depositConfigPda,
+          depositWallet: depositWallet.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          depositAta: depositAta,
+          mint: SystemProgram.programId,
+          referenceMarker: marker,
+        })
+        .signers([payer])
+        .rpc();
+
+    // Another wallet claiming the reference first doesn't block the payer
+    const other = Keypair.generate();
+    await airdrop(other.publicKey, 1);
+    await deposit(other);
+    await expectError(
+      deposit(other, markerPda(user.publicKey)),
+      "InvalidReferenceAccount"
+    );
+
+    await deposit();
+    const marker = await program.account.paymentReference.fetch(
+      markerPda(user.publicKey)
+    );
+    expect(marker.reference).to.deep.equal(reference);
+    expect(marker.payer.equals(user.publicKey)).to.be.true;
+
+    await expectError(deposit(), "ReferenceAlreadyUsed");
+
+    // References are shared between deposits and membership payments
+    await expectError(
+      program.methods
+        .payMembership(
+          0,
+          SystemProgram.programId,
+          MONTHLY_SOL,
+          null,
+          null,
+          reference
+        )
+        .accounts({
+          user: user.publicKey,
+          cfg: membershipConfigPda,
+          membership: membershipPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: userAta,
+          treasuryAta: treasuryAta,
+          mint: SystemProgram.programId,
+          referenceMarker: markerPda(user.publicKey),
+        })
+        .signers([user])
+        .rpc(),
+      "ReferenceAlreadyUsed"
+    );
+  });
+
+  it("Accepts SOL payments without token accounts", async () => {
+    const MONTHLY_SOL = toLamports(0.1);
+    const DEPOSIT_SOL = toLamports(0.01);
+    const payer = Keypair.generate();
+    await airdrop(payer.publicKey, 2);
+    const [payerPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("membership"), payer.publicKey.toBuffer()],
+      program.programId
+    );
+
+    const pay = () =>
+      program.methods
+        .payMembership(
+          0,
+          SystemProgram.programId,
+          MONTHLY_SOL,
+          null,
+          null,
+          null
+        )
+        .accounts({
+          user: payer.publicKey,
+          cfg: membershipConfigPda,
+          membership: payerPda,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: null,
+          treasuryAta: null,
+          mint: SystemProgram.programId,
+        })
+        .signers([payer])
+        .rpc();
+    await pay();
+    const membership = await program.account.membershipState.fetch(payerPda);
+    expect(membership.user.equals(payer.publicKey)).to.be.true;
+
+    // Refunds and plan changes in SOL need no token accounts either
+    const [vaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("refund_vault")],
+      program.programId
+    );
+    await airdrop(vaultPda, 1);
+    const payerBefore = await provider.connection.getBalance(payer.publicKey);
+    await program.methods
+      .refundMembership()
+      .accounts({
+        authority: admin.publicKey,
+        cfg: membershipConfigPda,
+        membership: payerPda,
+        vault: vaultPda,
+        recipient: payer.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        vaultAta: null,
+        recipientAta: null,
+        mint: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+    expect(await provider.connection.getBalance(payer.publicKey)).to.equal(
+      payerBefore + MONTHLY_SOL.toNumber()
+    );
+
+    await pay();
+    await program.methods
+      .changePlan({ yearly: {} }, toLamports(1.0))
+      .accounts({
+        user: payer.publicKey,
+        cfg: membershipConfigPda,
+        membership: payerPda,
+        treasury: treasury.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        userAta: null,
+        treasuryAta: null,
+        mint: SystemProgram.programId,
+      })
+      .signers([payer])
+      .rpc();
+    const changed = await program.account.membershipState.fetch(payerPda);
+    expect(changed.plan).to.deep.equal({ yearly: {} });
+
+    const depositWalletBefore = await provider.connection.getBalance(
+      depositWallet.publicKey
+    );
+    const deposit = (mint: PublicKey, amount: anchor.BN) =>
+      program.methods
+        .deposit(mint, amount, null)
+        .accounts({
+          user: payer.publicKey,
+          cfg: depositConfigPda,
+          depositWallet: depositWallet.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          userAta: null,
+          depositAta: null,
+          mint,
+        })
+        .signers([payer])
+        .rpc();
+    await deposit(SystemProgram.programId, DEPOSIT_SOL);
+    expect(
+      await provider.connection.getBalance(depositWallet.publicKey)
+    ).to.equal(depositWalletBefore + DEPOSIT_SOL.toNumber());
+
+    // SPL deposits still need their token accounts
+    await expectError(
+      deposit(testMint, new anchor.BN(1_000)),
+      "InvalidTokenAccount"
+    );
+  });
 });